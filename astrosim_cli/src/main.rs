@@ -3,7 +3,8 @@ extern crate serde;
 extern crate structopt;
 use astrosim_lib::prelude::*;
 use serde::Deserialize;
-use std::path::PathBuf;
+use std::io::Write;
+use std::path::{Path, PathBuf};
 use structopt::StructOpt;
 
 #[derive(StructOpt)]
@@ -29,30 +30,206 @@ struct Args {
 	#[structopt(long, default_value = "0.03")]
 	target_error: f64,
 
-	/// Number of times to save the output.
+	/// Number of times to save scheduled outputs (positions, density, diagnostics),
+	/// evenly spaced over the run time.
 	#[structopt(long, default_value = "300")]
 	outputs: u32,
 
-	/// Render this portion of the world.
+	/// Space the scheduled outputs logarithmically, from this time to the end of the run.
+	#[structopt(long)]
+	log_start: Option<f64>,
+
+	/// Save scheduled outputs at these times (comma separated), instead of evenly spaced.
+	#[structopt(long, require_delimiter = true)]
+	output_times: Vec<f64>,
+
+	/// Render this portion of the world (half the image width, in world units).
 	#[structopt(long, default_value = "2.0")]
 	render_scale: f64,
 
-	/// Render this number of pixels (for image width and height).
+	/// Render this number of pixels (image width, and height unless --render-height is given).
 	#[structopt(long, default_value = "512")]
 	render_pixels: u32,
 
+	/// Rendered image height in pixels, for non-square images.
+	#[structopt(long)]
+	render_height: Option<u32>,
+
+	/// Center of the rendered world (x,y; use --render-center=-1,0 for negative x).
+	#[structopt(long, require_delimiter = true)]
+	render_center: Vec<f64>,
+
+	/// Rotate the rendered world counter-clockwise by this angle (degrees).
+	#[structopt(long, default_value = "0")]
+	render_rotation: f64,
+
 	/// Enable writing timestep information to output_dir/timesteps.txt.
 	#[structopt(long)]
 	timesteps: bool,
 
+	/// Enable writing energy, momentum and their drift to output_dir/diagnostics.txt,
+	/// on the output schedule.
+	#[structopt(long)]
+	diagnostics: bool,
+
+	/// Enable writing particle positions to output_dir/positions.txt, on the output schedule.
+	#[structopt(long)]
+	positions: bool,
+
+	/// Enable writing time, positions and velocities as NumPy arrays
+	/// (output_dir/time.npy, pos.npy, vel.npy, mass.npy), on the output schedule.
+	#[structopt(long)]
+	npy: bool,
+
+	/// Like --npy, but bundle the arrays into output_dir/trajectory.npz at the end of the run.
+	#[structopt(long)]
+	npz: bool,
+
+	/// Enable writing snapshots to output_dir/snapshots.bin, on the output schedule.
+	#[structopt(long)]
+	snapshots: bool,
+
+	/// Write JSON Lines to stdout, on the output schedule: time, dt and error estimate.
+	/// The banner goes to stderr instead.
+	#[structopt(long)]
+	json: bool,
+
+	/// With --json: include particle states.
+	#[structopt(long)]
+	json_particles: bool,
+
+	/// With --json: include energy, momentum and their drift.
+	#[structopt(long)]
+	json_diagnostics: bool,
+
 	/// Write particle positions to output_dir/positions.txt every N time steps.
 	#[structopt(long, short, default_value = "0")]
 	positions_every: u32,
 
-	/// Render particle positions as image at this interval.
+	/// Enable rendering particle density images, on the output schedule.
+	#[structopt(long)]
+	render: bool,
+
+	/// Render particle density images at this interval, instead of on the output schedule.
 	#[structopt(long, short, default_value = "0")]
 	render_every: f64,
 
+	/// Accumulate density images on this many threads (0: all cores).
+	#[structopt(long, default_value = "1")]
+	render_threads: usize,
+
+	/// Tone-map and save rendered images on a background thread, overlapping with the simulation.
+	#[structopt(long)]
+	render_background: bool,
+
+	/// Integrate rendered density and --histogram over time with the trapezoidal rule,
+	/// interpolating between steps, instead of weighting each step by the interval before it.
+	#[structopt(long)]
+	trapezoid: bool,
+
+	/// Render in a frame co-rotating with this body (by name) about its centre of mass with the most massive other body,
+	/// or, given two bodies (origin,axis),
+	/// centered on the first and rotating with the line to the second.
+	#[structopt(long, require_delimiter = true)]
+	co_rotate: Vec<String>,
+
+	/// Render in unwrapped polar projection around this body (by name):
+	/// azimuth horizontally, radius vertically (see --polar-range).
+	#[structopt(long)]
+	polar: Option<String>,
+
+	/// With --polar: use the semi-major axis instead of the radius.
+	#[structopt(long)]
+	polar_a: bool,
+
+	/// With --polar: the radial range (min,max). Default: 0 to --render-scale.
+	#[structopt(long, require_delimiter = true)]
+	polar_range: Vec<f64>,
+
+	/// With --polar: logarithmic radial axis (requires min > 0).
+	#[structopt(long)]
+	polar_log: bool,
+
+	/// Particle deposit kernel for rendering: nearest, cic, tsc,
+	/// gaussian:R (R in world units) or gaussian:Rpx (R in pixels).
+	#[structopt(long, default_value = "nearest")]
+	kernel: Kernel,
+
+	/// Tone mapping of rendered images: linear, sqrt, log, asinh or equalize.
+	#[structopt(long, default_value = "sqrt")]
+	stretch: Stretch,
+
+	/// Render values below this percentile black (0: only zero density is black).
+	#[structopt(long, default_value = "0")]
+	clip_low: f32,
+
+	/// Render values above this percentile at full brightness.
+	#[structopt(long, default_value = "100")]
+	clip_high: f32,
+
+	/// Colormap for rendered images: gray, viridis, magma or inferno.
+	#[structopt(long, default_value = "gray")]
+	colormap: Colormap,
+
+	/// File format of rendered images: png, png16 (16-bit, tone-mapped), pfm or fits
+	/// (raw 32-bit float density; fits includes time and world coordinates).
+	#[structopt(long, default_value = "png")]
+	render_format: DensityFormat,
+
+	/// Annotations on rendered frames: time, count, label:TEXT, scalebar:LENGTH,
+	/// crosshair:NAME, circle:X:Y:RADIUS or orbit:NAME[:PRIMARY] (osculating orbit).
+	/// E.g. time,count,scalebar:1,orbit:jupiter.
+	#[structopt(long, require_delimiter = true)]
+	overlay: Vec<Overlay>,
+
+	/// Magnification of overlay text.
+	#[structopt(long, default_value = "1")]
+	overlay_scale: u32,
+
+	/// Draw fading trails of the recent positions of these bodies (by name) on rendered frames.
+	#[structopt(long, require_delimiter = true)]
+	trails: Vec<String>,
+
+	/// With --trails: number of points per trail (about one per pixel moved).
+	#[structopt(long, default_value = "500")]
+	trail_length: usize,
+
+	/// Accumulate a histogram of orbital elements over the run, written on the output schedule
+	/// to output_dir/histogram.csv (and histogram.png if 2D). Axes are element:min:max:bins,
+	/// with element a, e or omega. E.g. a:0.2:1:200 or a:0.2:1:200,e:0:0.5:100.
+	#[structopt(long, require_delimiter = true)]
+	histogram: Vec<Axis>,
+
+	/// Orbital elements for --histogram are around this body (default: the most massive).
+	#[structopt(long)]
+	histogram_primary: Option<String>,
+
+	/// Only bodies with this tag are counted in --histogram (default: all bodies without a name, e.g. asteroids).
+	#[structopt(long)]
+	histogram_tag: Option<String>,
+
+	/// Write SVG plots of the particles to output_dir/snapshot_<step>.svg on the output schedule,
+	/// with the same viewport as rendered images. Particles are coloured by tag.
+	#[structopt(long)]
+	svg: bool,
+
+	/// With --svg: trace the osculating orbits of these bodies (by name).
+	#[structopt(long, require_delimiter = true)]
+	svg_orbits: Vec<String>,
+
+	/// Write rendered frames to this animated .gif or .png file in output_dir,
+	/// instead of individual images.
+	#[structopt(long)]
+	animation: Option<String>,
+
+	/// Animation frame rate (frames per second).
+	#[structopt(long, default_value = "25")]
+	fps: f64,
+
+	/// Animation colour quantisation: gray, adaptive or truecolor (.png only).
+	#[structopt(long, default_value = "gray")]
+	palette: Palette,
+
 	/// Manually specify output directory.
 	#[structopt(long, short)]
 	output_dir: Option<String>,
@@ -60,6 +237,22 @@ struct Args {
 	/// Do not remove net momentum from particles (allowing for systematic drift).
 	#[structopt(long)]
 	net_momentum: bool,
+
+	/// Read input files without header line as orbital elements
+	/// (m, parent, a, e, omega, M) instead of positions and velocities (m, x, y, vx, vy).
+	#[structopt(long)]
+	elements: bool,
+
+	/// Save a checkpoint to output_dir/checkpoint.bin at this simulated time interval.
+	#[structopt(long, default_value = "0")]
+	checkpoint_every: f64,
+
+	/// Resume from a checkpoint file instead of starting from input files.
+	/// The run time (-t) still counts from the start of the original run.
+	/// Time step settings are restored from the checkpoint.
+	#[structopt(long)]
+	resume: Option<String>,
+
 	/// Files to process
 	#[structopt(name = "FILE")]
 	files: Vec<String>,
@@ -75,7 +268,19 @@ fn main() {
 fn main_checked() -> Result<()> {
 	let args = Args::from_args();
 
-	let mut particles = load_particle_files(&args.files)?;
+	let mut sim = match &args.resume {
+		Some(checkpoint) => Stepper::<BruteForce>::load_checkpoint(checkpoint)?,
+		None => {
+			let layout = if args.elements { Layout::Elements } else { Layout::Cartesian };
+			let (particles, meta) = load_particle_files(&args.files, layout)?;
+			let mut sim = Stepper::new(particles, BruteForce::new()).with_meta(meta);
+			sim.dt = args.initial_dt;
+			sim.min_dt = args.min_dt;
+			sim.max_dt = args.max_dt;
+			sim.target_error = args.target_error;
+			sim
+		}
+	};
 
 	//if !args.net_momentum {
 	//	// A residual net momentum would cause a systematic drift.
@@ -83,88 +288,349 @@ fn main_checked() -> Result<()> {
 	//}
 
 	//sort_by_mass(&mut particles);
-	//println!("{:?}", &particles[0]);
 
 	let output_dir = output_dir(&args);
+	let schedule = output_schedule(&args)?;
+
+	// Human-readable progress goes to stderr when stdout is used for JSON Lines.
+	let mut log: Box<dyn Write> = if args.json { Box::new(std::io::stderr()) } else { Box::new(std::io::stdout()) };
 
-	println!("input files:           {}", &args.files.join(","));
-	println!("particles:             {}", particles.len());
-	println!("net momentum removed:  {}", !args.net_momentum);
-	println!("run time:              {}", args.time);
-	println!("min time step:         {:e}", args.min_dt);
-	println!("max time step:         {:e}", args.max_dt);
-	println!("target relative error: {:e}", args.target_error);
-	println!("output dir:            {}", &output_dir.to_string_lossy());
-	println!("render every:          {} t", args.render_every);
-	println!("positions every:       {} th time step", args.positions_every);
-	println!("timesteps.txt:         {}", args.timesteps);
-
-	let mut sim = Stepper::new(particles, BruteForce::boxed());
-	sim.dt = args.initial_dt;
-	sim.min_dt = args.min_dt;
-	sim.max_dt = args.max_dt;
-	sim.target_error = args.target_error;
-
-	let mut outputs = Outputs::new(output_dir)? //
+	if let Some(checkpoint) = &args.resume {
+		writeln!(log, "resumed from:          {} (t={})", checkpoint, sim.time())?;
+	} else {
+		writeln!(log, "input files:           {}", &args.files.join(","))?;
+	}
+	writeln!(log, "particles:             {}", sim.particles().len())?;
+	writeln!(log, "named bodies:          {}", named_bodies(sim.meta()).join(","))?;
+	writeln!(log, "net momentum removed:  {}", !args.net_momentum)?;
+	writeln!(log, "run time:              {}", args.time)?;
+	writeln!(log, "min time step:         {:e}", sim.min_dt)?;
+	writeln!(log, "max time step:         {:e}", sim.max_dt)?;
+	writeln!(log, "target relative error: {:e}", sim.target_error)?;
+	writeln!(log, "output dir:            {}", &output_dir.to_string_lossy())?;
+	writeln!(log, "output schedule:       {:?}", &schedule)?;
+	writeln!(log, "render every:          {} t", args.render_every)?;
+	writeln!(log, "animation:             {}", args.animation.as_deref().unwrap_or("none"))?;
+	writeln!(log, "positions every:       {} th time step", args.positions_every)?;
+	writeln!(log, "npy arrays:            {}", args.npy || args.npz)?;
+	writeln!(log, "npz bundle:            {}", args.npz)?;
+	writeln!(log, "snapshots.bin:         {}", args.snapshots)?;
+	writeln!(log, "json lines:            {}", args.json)?;
+	writeln!(log, "timesteps.txt:         {}", args.timesteps)?;
+	writeln!(log, "diagnostics.txt:       {}", args.diagnostics)?;
+	writeln!(log, "histogram.csv:         {}", !args.histogram.is_empty())?;
+	writeln!(log, "checkpoint every:      {} t", args.checkpoint_every)?;
+
+	let outputs = match &args.resume {
+		Some(_) => Outputs::resume(&output_dir, sim.time())?,
+		None => Outputs::new(&output_dir)?,
+	};
+	if args.positions && args.positions_every != 0 {
+		return err("--positions and --positions-every both write positions.txt: use one");
+	}
+	let mut outputs = outputs //
 		.with_timesteps(args.timesteps)?
-		.with_positions_every(args.positions_every)?
-		.with_density(args.render_pixels)?
-		.with_render_every(args.render_every)?;
+		.with_positions_every(args.positions_every)?;
+	if args.positions {
+		outputs = outputs.with_positions(schedule.clone())?;
+	}
+	if args.npy || args.npz {
+		outputs = outputs.with_npy(schedule.clone(), args.npz)?;
+	}
+	if args.snapshots {
+		outputs = outputs.with_snapshots(schedule.clone(), &Units::default())?;
+	}
+	if args.json {
+		outputs = outputs.with_json_lines(schedule.clone(), args.json_particles, args.json_diagnostics);
+	}
+	if args.render || args.render_every != 0.0 {
+		let render_schedule = if args.render_every != 0.0 {
+			Schedule::interval(args.render_every).map_err(|e| error(format!("--render-every: {}", e)))?
+		} else {
+			schedule.clone()
+		};
+		if !(0.0 <= args.clip_low && args.clip_low < args.clip_high && args.clip_high <= 100.0) {
+			return err(format!("--clip-low, --clip-high: want 0 <= low < high <= 100, got {}, {}", args.clip_low, args.clip_high));
+		}
+		let tone_map = ToneMap::new().with_stretch(args.stretch).with_clip(args.clip_low, args.clip_high).with_colormap(args.colormap);
+		let (w, h) = render_size(&args);
+		let mut frames = DensityFrames::new(outputs.output_dir(), w, h, render_viewport(&args)?)
+			.with_frame(render_frame(&args, sim.particles(), sim.meta())?)
+			.with_kernel(args.kernel)
+			.with_tone_map(tone_map)
+			.with_format(args.render_format)
+			.with_threads(args.render_threads)
+			.with_background(args.render_background)
+			.with_exposure(Exposure::new().with_trapezoid(args.trapezoid))
+			.with_overlays(
+				Overlays {
+					items: args.overlay.clone(),
+					..Overlays::new()
+				}
+				.with_text_scale(args.overlay_scale),
+			);
+		if !args.trails.is_empty() {
+			for name in &args.trails {
+				index_of(sim.meta(), name).ok_or_else(|| error(format!("--trails: no body named {:?}", name)))?;
+			}
+			let selection = Selection::names(sim.meta(), &args.trails);
+			frames = frames.with_trails(Trails::new(selection, args.trail_length));
+		}
+		if let Some((polar, center)) = render_polar(&args, sim.meta())? {
+			frames = frames.with_polar(polar, center);
+		}
+		if let Some(file) = &args.animation {
+			frames = frames.with_animation(Animation::create(outputs.output_dir().join(file), w, h, args.fps, args.palette)?);
+		}
+		outputs = outputs.with_density_frames(render_schedule, frames);
+	}
+	if args.svg {
+		outputs = outputs.with_svg(schedule.clone(), svg_plot(&args, sim.meta())?);
+	}
+	if let Some((hist, primary)) = histogram(&args, sim.particles(), sim.meta())? {
+		let selection = match &args.histogram_tag {
+			Some(tag) => Selection::tag(sim.meta(), tag),
+			None => Selection::matching(sim.meta(), |m| m.name.is_none()),
+		};
+		let exposure = Exposure::new().with_trapezoid(args.trapezoid);
+		outputs = outputs.with_histogram(schedule.clone(), hist, primary, selection, exposure);
+	}
+	if args.diagnostics {
+		outputs = outputs.with_diagnostics(schedule.clone())?;
+	}
+	if args.checkpoint_every != 0.0 {
+		let file = output_dir.join(Stepper::<BruteForce>::CHECKPOINT_FILE);
+		outputs = outputs.with_sink(
+			Schedule::interval(args.checkpoint_every).map_err(|e| error(format!("--checkpoint-every: {}", e)))?,
+			Box::new(Checkpoints::new(file)),
+		);
+	}
 
 	let start = std::time::Instant::now();
 
-	sim.advance_with_output(args.time, &mut outputs)?;
+	// Checkpoints are saved from within the output callback,
+	// which does not affect the time stepping,
+	// so that resumed runs are identical to uninterrupted ones.
+	sim.advance_until_with_output(args.time, &mut outputs)?;
 
 	let duration = start.elapsed();
 	let steps_per_sec = sim.step_count() as f64 / duration.as_secs_f64();
 
 	outputs.close()?;
 
-	println!("done in:               {:.2}s", duration.as_secs_f64());
-	println!("steps per second:      {:.1}", steps_per_sec);
+	writeln!(log, "done in:               {:.2}s", duration.as_secs_f64())?;
+	writeln!(log, "steps per second:      {:.1}", steps_per_sec)?;
 
 	Ok(())
 }
 
 // output directory: first input file, but with extension ".out",
+// or the directory of the checkpoint being resumed,
 // unless explicitly overridden by flag --output-dir.
 fn output_dir(args: &Args) -> PathBuf {
 	if let Some(dir) = &args.output_dir {
 		PathBuf::from(dir)
+	} else if let Some(checkpoint) = &args.resume {
+		match Path::new(checkpoint).parent() {
+			Some(dir) => dir.into(),
+			None => PathBuf::from("."),
+		}
 	} else {
 		PathBuf::from(&args.files[0]).with_extension("out")
 	}
 }
 
+// When to save scheduled outputs:
+// at explicit times (--output-times), logarithmically spaced (--log-start),
+// or evenly spaced over the run time.
+fn output_schedule(args: &Args) -> Result<Schedule> {
+	if args.outputs == 0 {
+		return err("--outputs must be > 0");
+	}
+	Ok(if !args.output_times.is_empty() {
+		Schedule::times(args.output_times.clone()).map_err(|e| error(format!("--output-times: {}", e)))?
+	} else if let Some(start) = args.log_start {
+		if !(start > 0.0 && start < args.time) {
+			return err(format!("--log-start must be between 0 and the run time, got {}", start));
+		}
+		Schedule::logarithmic(start, args.time, args.outputs)
+	} else {
+		Schedule::evenly(args.time, args.outputs).map_err(|e| error(format!("--time: {}", e)))?
+	})
+}
+
+// Rendered image width and height in pixels.
+fn render_size(args: &Args) -> (u32, u32) {
+	(args.render_pixels, args.render_height.unwrap_or(args.render_pixels))
+}
+
+// Rendered part of the world, with the aspect ratio of the rendered images.
+fn render_viewport(args: &Args) -> Result<Viewport> {
+	let center = match args.render_center[..] {
+		[] => vec2(0.0, 0.0),
+		[x, y] => vec2(x, y),
+		_ => return err(format!("--render-center must be x,y, got {:?}", &args.render_center)),
+	};
+	let (w, h) = render_size(args);
+	if w == 0 || h == 0 {
+		return err("--render-pixels and --render-height must be > 0");
+	}
+	Ok(Viewport::from_scale(args.render_scale)
+		.fit(w, h)
+		.with_center(center)
+		.with_rotation(args.render_rotation.to_radians()))
+}
+
+// Reference frame for rendering.
+fn render_frame(args: &Args, particles: &[Particle], meta: &[Meta]) -> Result<ReferenceFrame> {
+	match &args.co_rotate[..] {
+		[] => Ok(ReferenceFrame::Inertial),
+		[body] => ReferenceFrame::co_rotating(particles, meta, body),
+		[origin, axis] => ReferenceFrame::line(meta, origin, axis),
+		_ => err(format!("--co-rotate must be one or two body names, got {:?}", &args.co_rotate)),
+	}
+}
+
+// Polar projection (and center body index) for rendering, if requested.
+fn render_polar(args: &Args, meta: &[Meta]) -> Result<Option<(Polar, usize)>> {
+	let center = match &args.polar {
+		None => return Ok(None),
+		Some(name) => index_of(meta, name).ok_or_else(|| error(format!("--polar: no body named {:?}", name)))?,
+	};
+	let (min, max) = match args.polar_range[..] {
+		[] => (0.0, args.render_scale),
+		[min, max] => (min, max),
+		_ => return err(format!("--polar-range must be min,max, got {:?}", &args.polar_range)),
+	};
+	if !(max > min && min >= 0.0) || (args.polar_log && min == 0.0) {
+		return err(format!("invalid --polar-range {},{}: need 0 <= min < max, and min > 0 with --polar-log", min, max));
+	}
+	let radial = if args.polar_a { Radial::SemiMajorAxis } else { Radial::Radius };
+	Ok(Some((Polar::new(radial, min, max).with_log(args.polar_log), center)))
+}
+
+// SVG plot with particles coloured by tag, and the requested orbits.
+fn svg_plot(args: &Args, meta: &[Meta]) -> Result<SvgPlot> {
+	const COLORS: [[u8; 3]; 6] = [[31, 119, 180], [255, 127, 14], [44, 160, 44], [214, 39, 40], [148, 103, 189], [140, 86, 75]];
+	let (w, h) = render_size(args);
+	let mut plot = SvgPlot::new(w, h, render_viewport(args)?).with_axes().with_labels();
+	let mut tags: Vec<&str> = meta.iter().filter_map(|m| m.tag.as_deref()).collect();
+	tags.sort_unstable();
+	tags.dedup();
+	for (tag, color) in tags.iter().zip(COLORS.iter().cycle()) {
+		plot = plot.with_group(Selection::tag(meta, tag), *color);
+	}
+	if !args.svg_orbits.is_empty() {
+		for name in &args.svg_orbits {
+			index_of(meta, name).ok_or_else(|| error(format!("--svg-orbits: no body named {:?}", name)))?;
+		}
+		plot = plot.with_orbits(Selection::names(meta, &args.svg_orbits));
+	}
+	Ok(plot)
+}
+
+// Orbital element histogram (and primary body index), if requested.
+fn histogram(args: &Args, particles: &[Particle], meta: &[Meta]) -> Result<Option<(Histogram, usize)>> {
+	let hist = match args.histogram[..] {
+		[] => return Ok(None),
+		[x] => Histogram::new(x),
+		[x, y] => Histogram::new_2d(x, y),
+		_ => return err("--histogram takes one or two axes"),
+	};
+	let primary = match &args.histogram_primary {
+		Some(name) => index_of(meta, name).ok_or_else(|| error(format!("--histogram-primary: no body named {:?}", name)))?,
+		None => (0..particles.len())
+			.max_by(|&i, &j| particles[i].mass.total_cmp(&particles[j].mass))
+			.ok_or_else(|| error("no particles"))?,
+	};
+	Ok(Some((hist, primary)))
+}
+
+// Names of the particles that have one.
+fn named_bodies(meta: &[Meta]) -> Vec<&str> {
+	meta.iter().filter_map(|m| m.name.as_deref()).collect()
+}
+
 // Load particles from one or more CSV files.
 // Particles from multiple files are concatenated.
 // Zero files is an error.
-fn load_particle_files(files: &[String]) -> Result<Vec<Particle>> {
-	if files.len() == 0 {
+//
+// Files without header line are read with the given layout.
+// Names, tags and radii are returned alongside, indexed like the particles.
+fn load_particle_files(files: &[String], layout: Layout) -> Result<(Vec<Particle>, Vec<Meta>)> {
+	if files.is_empty() {
 		return err("need at least one input file (CSV with mass, positions, velocities)");
 	}
 	let mut particles = Vec::new();
+	let mut meta = Vec::new();
 	for file in files {
-		particles.append(&mut load_particle_file(file)?)
+		load_particle_file(file, layout, &mut particles, &mut meta)?;
+	}
+	Ok((particles, meta))
+}
+
+// Column layout of files without header line.
+#[derive(Clone, Copy, Debug, PartialEq)]
+enum Layout {
+	// mass, position_x, position_y, velocity_x, velocity_y
+	Cartesian,
+	// mass, parent, semi-major axis, eccentricity, argument of periapsis, mean anomaly
+	Elements,
+}
+
+impl Layout {
+	// Column names implied by the layout.
+	fn header(self) -> csv::StringRecord {
+		match self {
+			Layout::Cartesian => csv::StringRecord::from(vec!["m", "x", "y", "vx", "vy"]),
+			Layout::Elements => csv::StringRecord::from(vec!["m", "parent", "a", "e", "omega", "M"]),
+		}
 	}
-	Ok(particles)
 }
 
-// Load particles from a CSV file with columns:
+// A header line is a first line that does not start with a number.
+fn is_header(record: &csv::StringRecord) -> bool {
+	match record.get(0) {
+		Some(first) => first.parse::<f64>().is_err(),
+		None => false,
+	}
+}
+
+// Load particles from a CSV file and append them to `particles` (and their names etc. to `meta`).
+//
+// The file may start with a header line naming the columns, in any order:
+//
+// 	name, m, x, y, vx, vy, radius, tag
 //
-// 	mass, position_x, position_y, velocity_x, velocity_y
+// Columns `m` and either `x, y, vx, vy` (position, velocity)
+// or `parent, a, e, omega, M` (orbital elements) are required, the others are optional.
+// The parent is the name, or else index, of the orbited body
+// among all particles loaded so far (possibly from earlier files).
+// Angles are in radians. `id` is an alias for `name`, `group` for `tag`.
 //
+// Files without header line have the columns given by `layout`.
 // Comment character is `#`.
-fn load_particle_file(fname: &str) -> Result<Vec<Particle>> {
+fn load_particle_file(fname: &str, layout: Layout, particles: &mut Vec<Particle>, meta: &mut Vec<Meta>) -> Result<()> {
 	#[derive(Debug, Deserialize)]
+	#[serde(deny_unknown_fields)]
 	struct Record {
+		#[serde(alias = "id")]
+		pub name: Option<String>,
 		pub m: f64,
-		pub x: f64,
-		pub y: f64,
-		pub vx: f64,
-		pub vy: f64,
+		pub x: Option<f64>,
+		pub y: Option<f64>,
+		pub vx: Option<f64>,
+		pub vy: Option<f64>,
+		pub parent: Option<String>,
+		pub a: Option<f64>,
+		pub e: Option<f64>,
+		pub omega: Option<f64>,
+		#[serde(rename = "M")]
+		pub mean_anomaly: Option<f64>,
+		pub radius: Option<f64>,
+		#[serde(alias = "group")]
+		pub tag: Option<String>,
 	}
-	let mut particles = Vec::new();
 	let msg = format!("load particles: {}", fname);
 	let mut rdr = csv::ReaderBuilder::new() //
 		.trim(csv::Trim::All)
@@ -172,9 +638,75 @@ fn load_particle_file(fname: &str) -> Result<Vec<Particle>> {
 		.has_headers(false)
 		.from_path(fname)
 		.msg(&msg)?;
-	for result in rdr.deserialize() {
-		let p: Record = result.msg(&msg)?;
-		particles.push(Particle::new(p.m, vec2(p.x, p.y), vec2(p.vx, p.vy)));
+	let mut header = layout.header();
+	for (i, result) in rdr.records().enumerate() {
+		let record = result.msg(&msg)?;
+		if i == 0 && is_header(&record) {
+			header = record;
+			continue;
+		}
+		let r: Record = record.deserialize(Some(&header)).msg(&msg)?;
+		let particle = match (r.x, r.y, r.vx, r.vy, r.parent, r.a, r.e, r.omega, r.mean_anomaly) {
+			(Some(x), Some(y), Some(vx), Some(vy), None, None, None, None, None) => Particle::new(r.m, vec2(x, y), vec2(vx, vy)),
+			(None, None, None, None, Some(parent), Some(a), Some(e), Some(omega), Some(mean_anomaly)) => {
+				let parent = match find_parent(particles, meta, &parent) {
+					Some(parent) => parent,
+					None => return err(format!("{}: parent {} not loaded (yet)", &msg, parent)),
+				};
+				if e == 1.0 {
+					return err(format!("{}: parabolic orbit has no semi-major axis", &msg));
+				}
+				if !((a > 0.0 && (0.0..1.0).contains(&e)) || (a < 0.0 && e > 1.0)) {
+					return err(format!("{}: need a > 0 with 0 <= e < 1 (ellipse) or a < 0 with e > 1 (hyperbola), got a={}, e={}", &msg, a, e));
+				}
+				Elements::new(parent.mass + r.m, a, e, omega, mean_anomaly).to_particle(r.m, parent)
+			}
+			_ => return err(format!("{}: need either columns x, y, vx, vy or parent, a, e, omega, M", &msg)),
+		};
+		particles.push(particle);
+		meta.push(Meta {
+			name: r.name,
+			tag: r.tag,
+			radius: r.radius.unwrap_or(0.0),
+		});
+	}
+	Ok(())
+}
+
+// Parent body referred to by name, or else by index.
+fn find_parent<'a>(particles: &'a [Particle], meta: &[Meta], parent: &str) -> Option<&'a Particle> {
+	match index_of(meta, parent) {
+		Some(i) => Some(&particles[i]),
+		None => parent.parse::<usize>().ok().and_then(|i| particles.get(i)),
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn load_elements() {
+		// one orbit around an already loaded sun.
+		let load = |a: f64, e: f64| {
+			let file = std::env::temp_dir().join(format!("astrosim_cli_elements_test_{}.csv", std::process::id()));
+			std::fs::write(&file, format!("m,parent,a,e,omega,M\n0,sun,{},{},0,1\n", a, e)).unwrap();
+			let mut particles = vec![Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0))];
+			let mut meta = vec![Meta::new().with_name("sun")];
+			let result = load_particle_file(file.to_str().unwrap(), Layout::Elements, &mut particles, &mut meta);
+			std::fs::remove_file(&file).unwrap();
+			result.map(|()| particles[1].clone())
+		};
+
+		// ellipse and hyperbola.
+		for &(a, e) in &[(1.0, 0.0), (1.0, 0.5), (-1.0, 1.5)] {
+			let p = load(a, e).unwrap();
+			assert!(p.pos.is_finite() && p.vel.is_finite());
+		}
+
+		// parabolic, negative e, and a inconsistent with e (semi-latus rectum <= 0).
+		for &(a, e) in &[(1.0, 1.0), (1.0, -0.5), (1.0, 1.5), (-1.0, 0.5), (0.0, 0.5), (1.0, f64::NAN)] {
+			assert!(load(a, e).is_err(), "a={}, e={}", a, e);
+		}
 	}
-	Ok(particles)
 }
@@ -0,0 +1,390 @@
+use super::prelude::*;
+use color_quant::NeuQuant;
+use std::fs::File;
+use std::io::{BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+use std::str::FromStr;
+
+/// Colour quantisation of animation frames.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Palette {
+	/// 256 gray levels (luma). Exact for grayscale frames, like density renders.
+	Gray,
+
+	/// Up to `colors` colours chosen by NeuQuant, `speed` 1 (best) to 30 (fastest).
+	/// GIF chooses a palette per frame, APNG one palette for all frames (from the first).
+	Adaptive { colors: usize, speed: i32 },
+
+	/// No quantisation: 8-bit RGBA (APNG only).
+	TrueColor,
+}
+
+impl Palette {
+	/// Adaptive palette with default speed.
+	pub fn adaptive(colors: usize) -> Self {
+		Palette::Adaptive { colors, speed: 10 }
+	}
+}
+
+impl FromStr for Palette {
+	type Err = Error;
+
+	/// "gray", "adaptive" (256 colours) or "truecolor".
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"gray" | "grey" => Ok(Palette::Gray),
+			"adaptive" => Ok(Palette::adaptive(256)),
+			"truecolor" => Ok(Palette::TrueColor),
+			_ => err(format!("unknown palette {:?}, want gray, adaptive or truecolor", s)),
+		}
+	}
+}
+
+/// Writes frames to a single animated GIF or PNG (APNG) file,
+/// instead of a file per frame. Frames are streamed to disk as they are added.
+///
+///   let mut anim = Animation::create("density.gif", 512, 512, 25.0, Palette::Gray)?;
+///   anim.add_density(&img)?;
+///   ...
+///   anim.finish()?;
+///
+pub struct Animation {
+	enc: Encoder,
+	width: u32,
+	height: u32,
+	palette: Palette,
+	delay: (u16, u16), // seconds per frame, as a fraction.
+}
+
+enum Encoder {
+	Gif(gif::Encoder<BufWriter<File>>),
+	Apng(Apng),
+}
+
+impl Animation {
+	/// Create an animation file playing at `fps` frames per second.
+	/// The format follows from the file extension: .gif, or .png / .apng.
+	pub fn create<P: AsRef<Path>>(file: P, width: u32, height: u32, fps: f64, palette: Palette) -> Result<Self> {
+		let file = file.as_ref();
+		let msg = format!("create {}", file.to_string_lossy());
+		if !(fps > 0.0 && fps <= 100.0) {
+			return err(format!("{}: frame rate must be in (0, 100], got {}", &msg, fps));
+		}
+		let ext = file.extension().map(|e| e.to_string_lossy().to_lowercase()).unwrap_or_default();
+		// Check everything before creating the file, so that errors do not leave an empty one.
+		let gif = match ext.as_str() {
+			"gif" => {
+				if width > 0xffff || height > 0xffff {
+					return err(format!("{}: GIF frame too large: {}x{}", &msg, width, height));
+				}
+				if let Palette::TrueColor = palette {
+					return err(format!("{}: GIF needs a palette: use gray or adaptive", &msg));
+				}
+				true
+			}
+			"png" | "apng" => false,
+			_ => return err(format!("{}: unknown animation format, want .gif, .png or .apng", &msg)),
+		};
+		let out = BufWriter::new(File::create(file).msg(&msg)?);
+
+		let enc = if gif {
+			let global_palette = match palette {
+				Palette::Adaptive { .. } => Vec::new(),
+				_ => gray_palette(),
+			};
+			let mut gif = gif::Encoder::new(out, width as u16, height as u16, &global_palette)?;
+			gif.set_repeat(gif::Repeat::Infinite)?;
+			Encoder::Gif(gif)
+		} else {
+			Encoder::Apng(Apng::new(out))
+		};
+
+		// GIF delays are in 1/100 s, APNG delays any fraction
+		// (of u16s: slow frame rates in 1/100 s, fast ones as 1/fps).
+		let delay = match enc {
+			Encoder::Gif(_) => (u16::max(1, f64::round(100.0 / fps) as u16), 100),
+			Encoder::Apng(_) if fps < 1.0 => (f64::round(100.0 / fps) as u16, 100),
+			Encoder::Apng(_) => (100, f64::round(100.0 * fps) as u16),
+		};
+		Ok(Self { enc, width, height, palette, delay })
+	}
+
+	/// Frame size: width, height.
+	pub fn size(&self) -> (u32, u32) {
+		(self.width, self.height)
+	}
+
+	/// Append a frame, which must have the animation's size.
+	pub fn add_frame(&mut self, frame: &image::RgbaImage) -> Result<()> {
+		if frame.dimensions() != (self.width, self.height) {
+			return err(format!("animation frame size {:?}, want {:?}", frame.dimensions(), (self.width, self.height)));
+		}
+		let (w, h) = (self.width, self.height);
+		match &mut self.enc {
+			Encoder::Gif(gif) => {
+				let mut f = match self.palette {
+					Palette::Gray => gif::Frame::from_indexed_pixels(w as u16, h as u16, &gray_pixels(frame), None),
+					Palette::Adaptive { colors, speed } => {
+						let nq = NeuQuant::new(speed, colors, frame.as_raw());
+						let pixels: Vec<u8> = frame.pixels().map(|p| nq.index_of(&p.0) as u8).collect();
+						gif::Frame::from_palette_pixels(w as u16, h as u16, &pixels, &nq.color_map_rgb(), None)
+					}
+					Palette::TrueColor => unreachable!(), // rejected by create
+				};
+				f.delay = self.delay.0;
+				gif.write_frame(&f)?;
+			}
+			Encoder::Apng(apng) => {
+				let png = match self.palette {
+					Palette::Gray => encode_png(w, h, png::ColorType::Grayscale, None, &gray_pixels(frame))?,
+					Palette::Adaptive { colors, speed } => {
+						let nq = apng.quantizer.get_or_insert_with(|| NeuQuant::new(speed, colors, frame.as_raw()));
+						let pixels: Vec<u8> = frame.pixels().map(|p| nq.index_of(&p.0) as u8).collect();
+						encode_png(w, h, png::ColorType::Indexed, Some(nq.color_map_rgb()), &pixels)?
+					}
+					Palette::TrueColor => encode_png(w, h, png::ColorType::RGBA, None, frame.as_raw())?,
+				};
+				apng.add_frame(&png, w, h, self.delay)?;
+			}
+		}
+		Ok(())
+	}
+
+	/// Append a density render, as `save_density` would write it.
+	pub fn add_density(&mut self, density: &Image<f32>) -> Result<()> {
+		self.add_frame(&density_image(density))
+	}
+
+	/// Finish writing the file. Must be called after the last frame.
+	pub fn finish(self) -> Result<()> {
+		match self.enc {
+			Encoder::Gif(gif) => drop(gif), // writes the trailer
+			Encoder::Apng(apng) => apng.finish()?,
+		}
+		Ok(())
+	}
+}
+
+fn gray_palette() -> Vec<u8> {
+	(0..=255).flat_map(|v| [v, v, v]).collect()
+}
+
+// Luma (ITU-R BT.601) of each pixel.
+fn gray_pixels(frame: &image::RgbaImage) -> Vec<u8> {
+	frame
+		.pixels()
+		.map(|p| f32::round(0.299 * p[0] as f32 + 0.587 * p[1] as f32 + 0.114 * p[2] as f32) as u8)
+		.collect()
+}
+
+// Encode a complete (single-frame) 8-bit PNG in memory.
+fn encode_png(width: u32, height: u32, color: png::ColorType, palette: Option<Vec<u8>>, data: &[u8]) -> Result<Vec<u8>> {
+	let mut buf = Vec::new();
+	{
+		let mut enc = png::Encoder::new(&mut buf, width, height);
+		enc.set_color(color);
+		enc.set_depth(png::BitDepth::Eight);
+		if let Some(palette) = palette {
+			enc.set_palette(palette);
+		}
+		enc.write_header()?.write_image_data(data)?;
+	}
+	Ok(buf)
+}
+
+const PNG_SIGNATURE: &[u8] = b"\x89PNG\r\n\x1a\n";
+
+/// Animated PNG writer.
+///
+/// Each frame is encoded as an ordinary PNG, whose image data is then
+/// re-wrapped in APNG frame chunks. The frame count in the animation control chunk
+/// is only known at the end, so it is patched in by `finish`.
+struct Apng {
+	out: BufWriter<File>,
+	frames: u32,
+	seq: u32,                    // APNG chunk sequence number.
+	actl_pos: u64,               // file offset of the acTL chunk.
+	quantizer: Option<NeuQuant>, // shared palette for Palette::Adaptive.
+}
+
+impl Apng {
+	fn new(out: BufWriter<File>) -> Self {
+		Self {
+			out,
+			frames: 0,
+			seq: 0,
+			actl_pos: 0,
+			quantizer: None,
+		}
+	}
+
+	fn add_frame(&mut self, png: &[u8], width: u32, height: u32, (delay_num, delay_den): (u16, u16)) -> Result<()> {
+		let chunks = png_chunks(png)?;
+		let mut fctl = Vec::with_capacity(26);
+		for v in &[self.seq, width, height, 0, 0] {
+			fctl.extend(&v.to_be_bytes());
+		}
+		fctl.extend(&delay_num.to_be_bytes());
+		fctl.extend(&delay_den.to_be_bytes());
+		fctl.extend(&[0, 0]); // dispose: none, blend: source
+		self.seq += 1;
+
+		if self.frames == 0 {
+			// The first frame is also the default image, for viewers without APNG support.
+			self.out.write_all(PNG_SIGNATURE)?;
+			let mut fctl_written = false;
+			for (name, data) in &chunks {
+				match name {
+					b"IHDR" => {
+						self.write_chunk(b"IHDR", data)?;
+						self.actl_pos = self.out.stream_position()?;
+						self.write_chunk(b"acTL", &actl(0))?;
+					}
+					b"PLTE" => self.write_chunk(b"PLTE", data)?,
+					b"IDAT" => {
+						if !fctl_written {
+							self.write_chunk(b"fcTL", &fctl)?;
+							fctl_written = true;
+						}
+						self.write_chunk(b"IDAT", data)?;
+					}
+					_ => (),
+				}
+			}
+		} else {
+			self.write_chunk(b"fcTL", &fctl)?;
+			let mut fdat = self.seq.to_be_bytes().to_vec();
+			for (_, data) in chunks.iter().filter(|(name, _)| name == b"IDAT") {
+				fdat.extend_from_slice(data);
+			}
+			self.seq += 1;
+			self.write_chunk(b"fdAT", &fdat)?;
+		}
+		self.frames += 1;
+		Ok(())
+	}
+
+	fn finish(mut self) -> Result<()> {
+		if self.frames == 0 {
+			return err("animation without frames");
+		}
+		self.write_chunk(b"IEND", &[])?;
+		self.out.seek(SeekFrom::Start(self.actl_pos))?;
+		self.write_chunk(b"acTL", &actl(self.frames))?;
+		self.out.flush()?;
+		Ok(())
+	}
+
+	fn write_chunk(&mut self, name: &[u8; 4], data: &[u8]) -> Result<()> {
+		let mut crc = crc32fast::Hasher::new();
+		crc.update(name);
+		crc.update(data);
+		self.out.write_all(&(data.len() as u32).to_be_bytes())?;
+		self.out.write_all(name)?;
+		self.out.write_all(data)?;
+		self.out.write_all(&crc.finalize().to_be_bytes())?;
+		Ok(())
+	}
+}
+
+// Animation control: number of frames, loop forever.
+fn actl(frames: u32) -> Vec<u8> {
+	let mut data = frames.to_be_bytes().to_vec();
+	data.extend(&0u32.to_be_bytes());
+	data
+}
+
+// Split a PNG file into (name, data) chunks.
+fn png_chunks(png: &[u8]) -> Result<Vec<([u8; 4], &[u8])>> {
+	if !png.starts_with(PNG_SIGNATURE) {
+		return err("not a PNG");
+	}
+	let mut chunks = Vec::new();
+	let mut rest = &png[PNG_SIGNATURE.len()..];
+	while rest.len() >= 12 {
+		let len = u32::from_be_bytes([rest[0], rest[1], rest[2], rest[3]]) as usize;
+		if rest.len() < 12 + len {
+			return err("truncated PNG chunk");
+		}
+		let name = [rest[4], rest[5], rest[6], rest[7]];
+		chunks.push((name, &rest[8..8 + len]));
+		rest = &rest[12 + len..];
+	}
+	Ok(chunks)
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn frames() -> Vec<image::RgbaImage> {
+		(0..3u8)
+			.map(|i| image::RgbaImage::from_fn(8, 4, |x, y| image::Rgba([x as u8 * 30, y as u8 * 60, i * 100, 255])))
+			.collect()
+	}
+
+	fn write(file: &Path, palette: Palette) -> Vec<u8> {
+		let mut anim = Animation::create(file, 8, 4, 10.0, palette).unwrap();
+		for f in frames() {
+			anim.add_frame(&f).unwrap();
+		}
+		anim.finish().unwrap();
+		let data = std::fs::read(file).unwrap();
+		std::fs::remove_file(file).unwrap();
+		data
+	}
+
+	#[test]
+	fn apng() {
+		let file = std::env::temp_dir().join(format!("astrosim_animation_test_{}.png", std::process::id()));
+		for &palette in &[Palette::Gray, Palette::adaptive(16), Palette::TrueColor] {
+			let data = write(&file, palette);
+			let chunks = png_chunks(&data).unwrap();
+			let names: Vec<&[u8]> = chunks.iter().map(|(n, _)| &n[..]).filter(|&n| n != b"PLTE").collect();
+			assert_eq!(names, vec![&b"IHDR"[..], b"acTL", b"fcTL", b"IDAT", b"fcTL", b"fdAT", b"fcTL", b"fdAT", b"IEND"]);
+			assert_eq!(chunks[1].1, &actl(3)[..]);
+
+			// sequence numbers: 0 .. 4, in fcTL and fdAT.
+			let seqs: Vec<u32> = chunks
+				.iter()
+				.filter(|(n, _)| n == b"fcTL" || n == b"fdAT")
+				.map(|(_, d)| u32::from_be_bytes([d[0], d[1], d[2], d[3]]))
+				.collect();
+			assert_eq!(seqs, vec![0, 1, 2, 3, 4]);
+
+			// still a valid PNG: the first frame.
+			let img = image::load_from_memory(&data).unwrap();
+			assert_eq!(image::GenericImageView::dimensions(&img), (8, 4));
+		}
+	}
+
+	#[test]
+	fn gif() {
+		let file = std::env::temp_dir().join(format!("astrosim_animation_test_{}.gif", std::process::id()));
+		for &palette in &[Palette::Gray, Palette::adaptive(16)] {
+			let data = write(&file, palette);
+			let decoder = image::gif::GifDecoder::new(&data[..]).unwrap();
+			use image::AnimationDecoder;
+			assert_eq!(decoder.into_frames().count(), 3);
+		}
+		assert!(Animation::create(&file, 8, 4, 10.0, Palette::TrueColor).is_err());
+	}
+
+	#[test]
+	fn create() {
+		let file = |ext: &str| std::env::temp_dir().join(format!("astrosim_animation_test_create_{}.{}", std::process::id(), ext));
+
+		// errors leave no file behind.
+		for (f, w, palette) in &[(file("gif"), 8, Palette::TrueColor), (file("gif"), 70000, Palette::Gray), (file("avi"), 8, Palette::Gray)] {
+			assert!(Animation::create(f, *w, 4, 10.0, *palette).is_err());
+			assert!(!f.exists());
+		}
+
+		// APNG frame delays, in seconds as a fraction.
+		let delay = |fps| Animation::create(file("png"), 8, 4, fps, Palette::Gray).unwrap().delay;
+		assert_eq!(delay(30.0), (100, 3000));
+		assert_eq!(delay(0.5), (200, 100));
+		assert_eq!(delay(0.004), (25000, 100));
+		std::fs::remove_file(file("png")).unwrap();
+	}
+}
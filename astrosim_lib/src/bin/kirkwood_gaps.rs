@@ -10,14 +10,31 @@ fn main() -> Result<()> {
 	fs::create_dir_all(&dir)?;
 
 	let particles = init_particles();
-	let mut sim = Stepper::new(particles, BruteForce::new());
+	let meta = init_meta(particles.len());
+	let mut sim = Stepper::new(particles, BruteForce::new()).with_meta(meta);
 	sim.target_error = 0.0001;
 	sim.min_dt = 0.000002;
 
 	let (w, h) = (512, 512);
-	let mut img = Image::<f32>::new(w, h);
 	//let mut overall = Image::<f32>::new(w, h);
-	let scale = 1.5;
+	let viewport = Viewport::from_scale(1.5);
+	// Long exposure: the gaps stand out against the asteroid belt without post-processing.
+	let belt = ToneMap::new().with_stretch(Stretch::Asinh(0.05)).with_clip(0.0, 99.5);
+	// Rotating with Jupiter, resonant structures stand still.
+	let frame = ReferenceFrame::line(sim.meta(), "sun", "jupiter")?;
+	// The gaps are dips in the distribution of semi-major axes.
+	let mut histogram = Histogram::new(Axis::new(Element::A, 0.2, 1.0, 400));
+	let mut composite = Composite::new(w, h, viewport)
+		.with_frame(frame)
+		.with_kernel(Kernel::Tsc) // anti-aliased, so that the asteroids do not flicker as they cross pixels.
+		.with_threads(0)
+		.with_layer(Layer::new(Selection::tag(sim.meta(), "asteroid")).with_color([0.7, 0.8, 1.0]).with_tone_map(belt))
+		.with_layer(Layer::new(Selection::name(sim.meta(), "sun")).with_color([1.0, 0.9, 0.4]))
+		.with_layer(Layer::new(Selection::name(sim.meta(), "jupiter")).with_color([1.0, 0.5, 0.2]).with_weight(0.6));
+	let asteroids = Selection::tag(sim.meta(), "asteroid");
+	let sun = index_of(sim.meta(), "sun").unwrap();
+
+	let mut exposure = Exposure::new().with_trapezoid(true);
 
 	let mut delta = 0.005;
 	//let delta = 2.0 * PI;
@@ -30,45 +47,45 @@ fn main() -> Result<()> {
 			delta = 4.0 * PI;
 		}
 		sim.advance_with_callback(delta, |s| {
-			accumulate_density(&mut img, &s.particles()[2..], scale, s.dt() as f32);
-			accumulate_density(&mut img, &s.particles()[0..1], scale, 0.3 * s.dt() as f32);
-			accumulate_density(&mut img, &s.particles()[1..2], scale, 0.3 * s.dt() as f32);
+			if let Some(weight) = exposure.add(s.time(), s.next_dt()) {
+				let particles = s.particles();
+				composite.accumulate(particles, weight);
+				histogram.accumulate(asteroids.select(particles), &particles[sun], weight);
+			}
 			// let new_py = s.particles()[1].pos.y;
 			// if py > 0.0 && new_py < 0.0 {
-			// 	accumulate_density(&mut img, &s.particles()[0..1], scale, 0.01);
-			// 	accumulate_density(&mut img, &s.particles()[1..2], scale, 0.01);
-			// 	accumulate_density(&mut img, &s.particles()[2..], scale, 1.0);
+			// 	accumulate_density(&mut img, &s.particles()[0..1], &viewport, 0.01);
+			// 	accumulate_density(&mut img, &s.particles()[1..2], &viewport, 0.01);
+			// 	accumulate_density(&mut img, &s.particles()[2..], &viewport, 1.0);
 			// 	save_density(&img, &dir.join(format!("density{:05}.png", i)))?;
-			// 	decay(&mut img);
+			// 	img.scale(0.98);
 			// }
 			// py = new_py;
 			Ok(())
 		})?;
+		if i % 100 == 99 {
+			composite.image().save(dir.join("kirkwood_gaps.png"))?;
+			histogram.write_csv(dir.join("semi_major_axis.csv"))?;
+		}
 	}
 
 	//for i in 0..1000 {
 	//	println!("i: {}, dt: {}", i, sim.dt);
 	//	let delta = 2.0 * PI;
-	//	sim.advance_with_callback(delta, |s| Ok(accumulate_density(&mut img, &s.particles()[1..], scale, s.dt() as f32)))?;
+	//	sim.advance_with_callback(delta, |s| Ok(accumulate_density(&mut img, &s.particles()[1..], &viewport, s.dt() as f32)))?;
 
 	//	save_density(&img, &dir.join(format!("density{:04}.jpg", i)))?;
-	//	clear(&mut img);
+	//	img.clear();
 	//}
 
 	Ok(())
 }
 
-//fn clear(img: &mut Image<f32>) {
-//	for p in img.pixels_mut() {
-//		*p = 0.0;
-//	}
-//}
-
-fn decay(img: &mut Image<f32>) {
-	//img.clear();
-	for p in img.pixels_mut() {
-		*p *= 0.98;
-	}
+// Names of the sun and jupiter, the rest are asteroids.
+fn init_meta(n: usize) -> Vec<Meta> {
+	let mut meta = vec![Meta::new().with_name("sun"), Meta::new().with_name("jupiter")];
+	meta.resize(n, Meta::new().with_tag("asteroid"));
+	meta
 }
 
 fn init_particles() -> Vec<Particle> {
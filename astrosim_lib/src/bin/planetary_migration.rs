@@ -6,45 +6,69 @@ use std::path::PathBuf;
 const NUM_ASTEROIDS: usize = 100;
 const ASTEROIDS_MASS: f64 = 3e-7;
 
+// Write all frames to this animation in the output directory (None: a PNG file per frame).
+// The animation is only complete when the run ends, not if it is interrupted.
+const ANIMATION: Option<&str> = None;
+
 fn main() -> Result<()> {
 	let dir = PathBuf::from("planetary_migration.out");
 	fs::create_dir_all(&dir)?;
 
 	let particles = init_particles();
-	let mut sim = Stepper::new(particles, PartialForce::new(2)); // TODO: fix sun
+	let meta = init_meta(particles.len());
+	let mut sim = Stepper::new(particles, PartialForce::new(2)).with_meta(meta); // TODO: fix sun
+	let jupiter = index_of(sim.meta(), "jupiter").unwrap();
+	let asteroids = Selection::tag(sim.meta(), "asteroid");
 	sim.target_error = 0.001;
 	sim.min_dt = 0.0001;
 
-	let (w, h) = (512, 512); // TODO: non-square crashes
+	let (w, h) = (640, 480);
 	let mut img = Image::<f32>::new(w, h);
-	let scale = 2.5;
+	let viewport = Viewport::from_scale(2.5).fit(w, h);
+	let mut animation = match ANIMATION {
+		Some(file) => Some(Animation::create(dir.join(file), w, h, 25.0, Palette::Gray)?),
+		None => None,
+	};
+	let overlays = Overlays::new()
+		.with(Overlay::Time)
+		.with(Overlay::ParticleCount)
+		.with(Overlay::ScaleBar(1.0))
+		.with(Overlay::Crosshair("jupiter".into()));
+	let mut exposure = Exposure::new();
+	let mut trails = Trails::new(Selection::name(sim.meta(), "jupiter"), 2000).with_color([255, 160, 60]).with_alpha(0.6);
 
 	for i in 0..100000 {
-		println!("{}, {}", sim.time(), sim.particles()[1].pos.len());
+		println!("{}, {}", sim.time(), sim.particles()[jupiter].pos.len());
 		sim.advance_with_callback(12.0, |s| {
-			accumulate_density(&mut img, &s.particles()[1..2], scale, 8.0 * s.dt() as f32);
-			accumulate_density(&mut img, &s.particles()[2..], scale, s.dt() as f32);
+			if let Some(weight) = exposure.add(s.time(), s.next_dt()) {
+				let particles = s.particles();
+				accumulate_density(&mut img, &particles[jupiter..jupiter + 1], &viewport, 8.0 * weight);
+				accumulate_density(&mut img, asteroids.select(particles), &viewport, weight);
+			}
+			trails.record(s.particles(), &viewport, w as usize, h as usize);
 			Ok(())
 		})?;
-		save_density(&img, &dir.join(format!("density{:04}.png", i)))?;
-		clear(&mut img);
+		let mut frame = density_image(&img);
+		trails.draw(&mut frame);
+		overlays.draw(&mut frame, sim.time(), sim.particles(), sim.meta(), &viewport);
+		match &mut animation {
+			Some(anim) => anim.add_frame(&frame)?,
+			None => frame.save(dir.join(format!("density{:04}.png", i)))?,
+		}
+		img.clear();
+	}
+	if let Some(anim) = animation {
+		anim.finish()?;
 	}
 
 	Ok(())
 }
 
-fn clear(img: &mut Image<f32>) {
-	//img.clear();
-	for p in img.pixels_mut() {
-		*p = 0.0;
-	}
-}
-
-fn decay(img: &mut Image<f32>) {
-	//img.clear();
-	for p in img.pixels_mut() {
-		*p *= 0.98;
-	}
+// Names of the sun and jupiter, the rest are asteroids.
+fn init_meta(n: usize) -> Vec<Meta> {
+	let mut meta = vec![Meta::new().with_name("sun"), Meta::new().with_name("jupiter")];
+	meta.resize(n, Meta::new().with_tag("asteroid"));
+	meta
 }
 
 fn init_particles() -> Vec<Particle> {
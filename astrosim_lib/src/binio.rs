@@ -0,0 +1,116 @@
+use super::prelude::*;
+use std::io::{Read, Write};
+
+/// Importing this trait adds methods to all Writers
+/// to write primitive values in little-endian binary format. E.g.:
+///
+///   w.write_f64(time)?;
+///
+pub trait WriteBin: Write {
+	fn write_u8(&mut self, v: u8) -> Result<()> {
+		Ok(self.write_all(&[v])?)
+	}
+
+	fn write_u32(&mut self, v: u32) -> Result<()> {
+		Ok(self.write_all(&v.to_le_bytes())?)
+	}
+
+	fn write_u64(&mut self, v: u64) -> Result<()> {
+		Ok(self.write_all(&v.to_le_bytes())?)
+	}
+
+	fn write_f64(&mut self, v: f64) -> Result<()> {
+		Ok(self.write_all(&v.to_le_bytes())?)
+	}
+
+	fn write_vec2(&mut self, v: vec2) -> Result<()> {
+		self.write_f64(v.x)?;
+		self.write_f64(v.y)
+	}
+
+	/// Length-prefixed UTF-8.
+	fn write_str(&mut self, v: &str) -> Result<()> {
+		self.write_u32(v.len() as u32)?;
+		Ok(self.write_all(v.as_bytes())?)
+	}
+
+	fn write_opt_str(&mut self, v: Option<&str>) -> Result<()> {
+		match v {
+			None => self.write_u8(0),
+			Some(v) => {
+				self.write_u8(1)?;
+				self.write_str(v)
+			}
+		}
+	}
+}
+
+impl<W: Write + ?Sized> WriteBin for W {}
+
+/// Counterpart of WriteBin.
+pub trait ReadBin: Read {
+	fn read_u8(&mut self) -> Result<u8> {
+		let mut buf = [0u8; 1];
+		self.read_exact(&mut buf)?;
+		Ok(buf[0])
+	}
+
+	fn read_u16(&mut self) -> Result<u16> {
+		let mut buf = [0u8; 2];
+		self.read_exact(&mut buf)?;
+		Ok(u16::from_le_bytes(buf))
+	}
+
+	fn read_u32(&mut self) -> Result<u32> {
+		let mut buf = [0u8; 4];
+		self.read_exact(&mut buf)?;
+		Ok(u32::from_le_bytes(buf))
+	}
+
+	fn read_u64(&mut self) -> Result<u64> {
+		let mut buf = [0u8; 8];
+		self.read_exact(&mut buf)?;
+		Ok(u64::from_le_bytes(buf))
+	}
+
+	fn read_f64(&mut self) -> Result<f64> {
+		let mut buf = [0u8; 8];
+		self.read_exact(&mut buf)?;
+		Ok(f64::from_le_bytes(buf))
+	}
+
+	fn read_vec2(&mut self) -> Result<vec2> {
+		Ok(vec2(self.read_f64()?, self.read_f64()?))
+	}
+
+	fn read_str(&mut self) -> Result<String> {
+		let len = self.read_u32()? as u64;
+		// Without allocating for a corrupt length first.
+		let mut buf = Vec::new();
+		self.take(len).read_to_end(&mut buf)?;
+		if buf.len() as u64 != len {
+			return err("truncated string");
+		}
+		Ok(String::from_utf8(buf)?)
+	}
+
+	fn read_opt_str(&mut self) -> Result<Option<String>> {
+		match self.read_u8()? {
+			0 => Ok(None),
+			1 => Ok(Some(self.read_str()?)),
+			x => err(format!("invalid optional string marker: {}", x)),
+		}
+	}
+
+	/// Read and check a magic number identifying the file type.
+	fn read_magic(&mut self, want: &[u8]) -> Result<()> {
+		let mut got = vec![0u8; want.len()];
+		self.read_exact(&mut got)?;
+		if got != want {
+			return err(format!("bad file type: want {:?}", String::from_utf8_lossy(want)));
+		}
+		Ok(())
+	}
+}
+
+impl<R: Read + ?Sized> ReadBin for R {}
@@ -1,5 +1,6 @@
 use super::prelude::*;
 
+#[derive(Default)]
 pub struct BruteForce();
 
 impl Forces for BruteForce {
@@ -79,4 +80,20 @@ mod test {
 		assert_eq!(acc[1], vec2(-1.6810835055998654, 0.7155417527999327));
 		assert_eq!(acc[2], vec2(0.0, -0.7155417527999327));
 	}
+
+	// Test the potential energy of 3 particles in a row,
+	// massless particles do not contribute.
+	//  *--*--*  *
+	#[test]
+	fn potential_energy() {
+		let p = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(2.0, vec2(1.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(4.0, vec2(2.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(0.0, vec2(3.0, 0.0), vec2(0.0, 0.0)),
+		];
+
+		let energy = (BruteForce()).potential_energy(&p);
+		assert_eq!(energy, -(2.0 + 4.0 / 2.0 + 8.0));
+	}
 }
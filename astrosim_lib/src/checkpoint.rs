@@ -0,0 +1,283 @@
+use super::binio::*;
+use super::prelude::*;
+use std::fs;
+use std::fs::File;
+use std::io::{BufWriter, Read, Write};
+use std::path::{Path, PathBuf};
+
+/// Forces that can be saved to, and restored from, a checkpoint.
+pub trait ForceConfig: Forces + Sized {
+	/// Identifies the force type in a checkpoint file.
+	const NAME: &'static str;
+
+	fn write_config(&self, w: &mut dyn Write) -> Result<()>;
+	fn read_config(r: &mut dyn Read) -> Result<Self>;
+}
+
+impl ForceConfig for BruteForce {
+	const NAME: &'static str = "BruteForce";
+
+	fn write_config(&self, _w: &mut dyn Write) -> Result<()> {
+		Ok(())
+	}
+
+	fn read_config(_r: &mut dyn Read) -> Result<Self> {
+		Ok(BruteForce::new())
+	}
+}
+
+impl ForceConfig for PartialForce {
+	const NAME: &'static str = "PartialForce";
+
+	fn write_config(&self, w: &mut dyn Write) -> Result<()> {
+		w.write_u64(self.cutoff_index() as u64)
+	}
+
+	fn read_config(r: &mut dyn Read) -> Result<Self> {
+		Ok(PartialForce::new(r.read_u64()? as usize))
+	}
+}
+
+const MAGIC: &[u8] = b"ASTROCKP";
+const VERSION: u32 = 1;
+
+// Each particle takes at least this many bytes:
+// mass, position, velocity, radius, name and tag markers, two accelerations.
+const MIN_PARTICLE_BYTES: usize = 8 + 16 + 16 + 8 + 1 + 1 + 16 + 16;
+
+impl<F: ForceConfig> Stepper<F> {
+	/// Checkpoint file name, in the output directory.
+	pub const CHECKPOINT_FILE: &'static str = "checkpoint.bin";
+
+	/// Save the complete simulation state to a binary file,
+	/// so that it can be resumed with `load_checkpoint`.
+	///
+	/// May be called from an `advance_with_callback` callback:
+	/// a run resumed from there is bit-identical to an uninterrupted run.
+	///
+	/// The file is written under a temporary name first,
+	/// so that a crash while saving does not destroy an earlier checkpoint.
+	pub fn save_checkpoint<P: AsRef<Path>>(&self, file: P) -> Result<()> {
+		let file = file.as_ref();
+		let tmp = file.with_extension("tmp");
+		let msg = format!("save checkpoint {}", tmp.to_string_lossy());
+		let mut w = BufWriter::new(File::create(&tmp).msg(&msg)?);
+		self.write_checkpoint(&mut w).map_err(|e| error(format!("{}: {}", &msg, e)))?;
+		w.flush().msg(&msg)?;
+		drop(w);
+		fs::rename(&tmp, file).msg(&format!("save checkpoint {}", file.to_string_lossy()))?;
+		Ok(())
+	}
+
+	/// Restore a simulation saved by `save_checkpoint`.
+	pub fn load_checkpoint<P: AsRef<Path>>(file: P) -> Result<Self> {
+		let file = file.as_ref();
+		let msg = format!("load checkpoint {}", file.to_string_lossy());
+		let bytes = fs::read(file).msg(&msg)?;
+		Self::read_checkpoint(&mut bytes.as_slice()).map_err(|e| error(format!("{}: {}", &msg, e)))
+	}
+
+	fn write_checkpoint(&self, w: &mut dyn Write) -> Result<()> {
+		w.write_all(MAGIC)?;
+		w.write_u32(VERSION)?;
+
+		w.write_str(F::NAME)?;
+		self.force.write_config(w)?;
+
+		w.write_f64(self.time)?;
+		w.write_f64(self.dt)?;
+		w.write_u64(self.step_count)?;
+		w.write_u8(self.adjust_pending as u8)?;
+		w.write_f64(self.target_error)?;
+		w.write_f64(self.min_dt)?;
+		w.write_f64(self.max_dt)?;
+
+		w.write_u64(self.particles.len() as u64)?;
+		for (p, m) in self.particles.iter().zip(&self.meta) {
+			w.write_f64(p.mass)?;
+			w.write_vec2(p.pos)?;
+			w.write_vec2(p.vel)?;
+			w.write_f64(m.radius)?;
+			w.write_opt_str(m.name.as_deref())?;
+			w.write_opt_str(m.tag.as_deref())?;
+		}
+		for &a in self.acc1.iter().chain(self.acc2.iter()) {
+			w.write_vec2(a)?;
+		}
+		Ok(())
+	}
+
+	// Read from the file contents, so that counts can be checked against the remaining length
+	// before allocating for them.
+	fn read_checkpoint(r: &mut &[u8]) -> Result<Self> {
+		r.read_magic(MAGIC)?;
+		let version = r.read_u32()?;
+		if version != VERSION {
+			return err(format!("unsupported checkpoint version {} (want {})", version, VERSION));
+		}
+
+		let force_name = r.read_str()?;
+		if force_name != F::NAME {
+			return err(format!("checkpoint has force {}, want {}", force_name, F::NAME));
+		}
+		let force = F::read_config(r)?;
+
+		let time = r.read_f64()?;
+		let dt = r.read_f64()?;
+		let step_count = r.read_u64()?;
+		let adjust_pending = r.read_u8()? != 0;
+		let target_error = r.read_f64()?;
+		let min_dt = r.read_f64()?;
+		let max_dt = r.read_f64()?;
+
+		let n = r.read_u64()?;
+		if n > (r.len() / MIN_PARTICLE_BYTES) as u64 {
+			return err(format!("truncated or corrupt: {} particles do not fit in {} bytes", n, r.len()));
+		}
+		let n = n as usize;
+		let mut particles = Vec::with_capacity(n);
+		let mut meta = Vec::with_capacity(n);
+		for _i in 0..n {
+			let mass = r.read_f64()?;
+			let pos = r.read_vec2()?;
+			let vel = r.read_vec2()?;
+			particles.push(Particle::new(mass, pos, vel));
+			meta.push(Meta {
+				radius: r.read_f64()?,
+				name: r.read_opt_str()?,
+				tag: r.read_opt_str()?,
+			});
+		}
+		let mut acc1 = Vec::with_capacity(n);
+		for _i in 0..n {
+			acc1.push(r.read_vec2()?);
+		}
+		let mut acc2 = Vec::with_capacity(n);
+		for _i in 0..n {
+			acc2.push(r.read_vec2()?);
+		}
+
+		Ok(Self {
+			particles,
+			meta,
+			force,
+			target_error,
+			min_dt,
+			max_dt,
+			dt,
+			step_count,
+			time,
+			acc1,
+			acc2,
+			adjust_pending,
+			end_time: time,
+			next_end_time: time,
+		})
+	}
+}
+
+/// Output sink saving a checkpoint when scheduled.
+///
+/// Checkpoints are saved at the first time step at or past each scheduled time,
+/// so that enabling them does not alter the time steps taken.
+pub struct Checkpoints {
+	file: PathBuf,
+}
+
+impl Checkpoints {
+	pub fn new<P: AsRef<Path>>(file: P) -> Self {
+		Self { file: file.as_ref().into() }
+	}
+}
+
+impl<F: ForceConfig> OutputSink<F> for Checkpoints {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		sim.save_checkpoint(&self.file)
+	}
+
+	fn exact_times(&self) -> bool {
+		false
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn particles() -> Vec<Particle> {
+		vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(1e-3, vec2(1.0, 0.0), vec2(0.0, 1.0)),
+			Particle::new(0.0, vec2(0.0, 0.6), vec2(-1.3, 0.0)),
+			Particle::new(0.0, vec2(-0.5, 0.1), vec2(0.2, -1.4)),
+		]
+	}
+
+	fn meta() -> Vec<Meta> {
+		vec![
+			Meta::new().with_name("sun"),
+			Meta::new().with_name("jupiter"),
+			Meta::new().with_tag("asteroid"),
+			Meta::new().with_tag("asteroid").with_radius(1e-5),
+		]
+	}
+
+	fn assert_identical<F: Forces>(a: &Stepper<F>, b: &Stepper<F>) {
+		assert_eq!(a.time().to_bits(), b.time().to_bits());
+		assert_eq!(a.dt().to_bits(), b.dt().to_bits());
+		assert_eq!(a.step_count(), b.step_count());
+		for (pa, pb) in a.particles().iter().zip(b.particles()) {
+			assert_eq!(pa.pos, pb.pos);
+			assert_eq!(pa.vel, pb.vel);
+		}
+		assert_eq!(a.meta(), b.meta());
+	}
+
+	// A run resumed from a checkpoint saved during advance
+	// must be bit-identical to an uninterrupted run.
+	#[test]
+	fn resume_bit_identical() {
+		let file = std::env::temp_dir().join(format!("astrosim_checkpoint_test_{}.bin", std::process::id()));
+		let end_time = 3.0;
+
+		let mut uninterrupted = Stepper::new(particles(), PartialForce::new(2)).with_meta(meta());
+		uninterrupted.target_error = 1e-3;
+		let mut saved = false;
+		uninterrupted
+			.advance_until_with_callback(end_time, |s| {
+				if !saved && s.time() > 1.0 {
+					s.save_checkpoint(&file)?;
+					saved = true;
+				}
+				Ok(())
+			})
+			.unwrap();
+
+		let mut resumed = Stepper::<PartialForce>::load_checkpoint(&file).unwrap();
+		resumed.advance_until_with_callback(end_time, |_| Ok(())).unwrap();
+		std::fs::remove_file(&file).unwrap();
+
+		assert_identical(&uninterrupted, &resumed);
+	}
+
+	#[test]
+	fn wrong_force() {
+		let file = std::env::temp_dir().join(format!("astrosim_checkpoint_test_force_{}.bin", std::process::id()));
+		Stepper::new(particles(), BruteForce::new()).save_checkpoint(&file).unwrap();
+		let result = Stepper::<PartialForce>::load_checkpoint(&file);
+		assert!(result.is_err());
+
+		// an absurd particle count is an error, not an allocation failure.
+		let mut bytes = Vec::new();
+		bytes.write_all(MAGIC).unwrap();
+		bytes.write_u32(VERSION).unwrap();
+		bytes.write_str(BruteForce::NAME).unwrap();
+		bytes.extend_from_slice(&[0; 8 + 8 + 8 + 1 + 8 + 8 + 8]);
+		bytes.write_u64(u64::MAX / 2).unwrap();
+		bytes.extend_from_slice(&[0; 1000]);
+		std::fs::write(&file, &bytes).unwrap();
+		let result = Stepper::<BruteForce>::load_checkpoint(&file);
+		std::fs::remove_file(&file).unwrap();
+		assert!(result.err().unwrap().to_string().contains("do not fit"));
+	}
+}
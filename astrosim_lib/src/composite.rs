@@ -0,0 +1,188 @@
+use super::prelude::*;
+
+/// Colour density image of several particle groups (layers), each with its own colour.
+///
+/// Each layer accumulates the density of its particles, is tone-mapped on its own
+/// (so that e.g. a single bright star does not drown out the asteroids),
+/// and is added to the image in its colour, scaled by its weight.
+///
+///   let mut composite = Composite::new(512, 512, Viewport::from_scale(1.5))
+///       .with_layer(Layer::new(Selection::tag(sim.meta(), "asteroid")).with_color([0.6, 0.8, 1.0]))
+///       .with_layer(Layer::new(Selection::name(sim.meta(), "jupiter")).with_color([1.0, 0.5, 0.1]).with_weight(0.5));
+///   let mut exposure = Exposure::new();
+///   sim.advance_with_callback(1.0, |s| Ok(if let Some(weight) = exposure.add(s.time(), s.next_dt()) { composite.accumulate(s.particles(), weight) }))?;
+///   composite.image().save("density.png")?;
+///
+pub struct Composite {
+	viewport: Viewport,
+	frame: ReferenceFrame,
+	kernel: Kernel,
+	threads: usize,
+	layers: Vec<Layer>,
+	w: u32,
+	h: u32,
+}
+
+/// A particle group in a `Composite`.
+#[derive(Clone)]
+pub struct Layer {
+	pub selection: Selection,
+	/// Linear RGB, 0..1.
+	pub color: [f32; 3],
+	pub weight: f32,
+	/// Tone map for this layer (the colormap is not used).
+	pub tone_map: ToneMap,
+	img: Image<f32>,
+	splat: ParallelSplat,
+}
+
+impl Composite {
+	/// Image of w x h pixels, without layers yet.
+	pub fn new(w: u32, h: u32, viewport: Viewport) -> Self {
+		Self {
+			viewport,
+			frame: ReferenceFrame::default(),
+			kernel: Kernel::default(),
+			threads: 1,
+			layers: Vec::new(),
+			w,
+			h,
+		}
+	}
+
+	/// Render in this (e.g. co-rotating) frame. The viewport is relative to the frame.
+	pub fn with_frame(mut self, frame: ReferenceFrame) -> Self {
+		self.frame = frame;
+		self
+	}
+
+	pub fn with_kernel(mut self, kernel: Kernel) -> Self {
+		self.kernel = kernel;
+		self
+	}
+
+	/// Accumulate on this many threads (0: all available cores), see `ParallelSplat`.
+	pub fn with_threads(mut self, threads: usize) -> Self {
+		self.threads = threads;
+		for layer in &mut self.layers {
+			layer.splat = ParallelSplat::new(threads);
+		}
+		self
+	}
+
+	pub fn with_layer(mut self, mut layer: Layer) -> Self {
+		layer.img = Image::new(self.w, self.h);
+		layer.splat = ParallelSplat::new(self.threads);
+		self.layers.push(layer);
+		self
+	}
+
+	pub fn layers(&self) -> &[Layer] {
+		&self.layers
+	}
+
+	/// Add `weight` (typically the time step) to each layer's density, for its particles.
+	pub fn accumulate(&mut self, particles: &[Particle], weight: f32) {
+		let viewport = self.frame.viewport(particles, &self.viewport);
+		for layer in &mut self.layers {
+			layer.splat.splat(&mut layer.img, particles, &layer.selection, &viewport, self.kernel, weight);
+		}
+	}
+
+	/// The layers, tone-mapped and composited.
+	pub fn image(&self) -> image::RgbaImage {
+		let mut rgb = Image::<[f32; 3]>::new(self.w, self.h);
+		for layer in &self.layers {
+			let norm = layer.tone_map.normalize(&layer.density());
+			for (c, &v) in rgb.pixels_mut().iter_mut().zip(norm.pixels()) {
+				for (c, &color) in c.iter_mut().zip(&layer.color) {
+					*c += layer.weight * color * v;
+				}
+			}
+		}
+		let u8 = |c: f32| (f32::clamp(c, 0.0, 1.0) * 255.0) as u8;
+		image::ImageBuffer::from_fn(self.w, self.h, |x, y| {
+			let c = rgb[y as usize][x as usize];
+			image::Rgba([u8(c[0]), u8(c[1]), u8(c[2]), 255])
+		})
+	}
+
+	/// Reset all layers to zero density.
+	pub fn clear(&mut self) {
+		for layer in &mut self.layers {
+			layer.img.clear();
+			layer.splat.clear();
+		}
+	}
+}
+
+impl Layer {
+	/// White layer with weight 1 and the default tone map.
+	pub fn new(selection: Selection) -> Self {
+		Self {
+			selection,
+			color: [1.0, 1.0, 1.0],
+			weight: 1.0,
+			tone_map: ToneMap::default(),
+			img: Image::new(0, 0),
+			splat: ParallelSplat::default(),
+		}
+	}
+
+	pub fn with_color(mut self, color: [f32; 3]) -> Self {
+		self.color = color;
+		self
+	}
+
+	pub fn with_weight(mut self, weight: f32) -> Self {
+		self.weight = weight;
+		self
+	}
+
+	pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+		self.tone_map = tone_map;
+		self
+	}
+
+	/// Accumulated density.
+	pub fn density(&self) -> Image<f32> {
+		let mut img = self.img.clone();
+		self.splat.add_to(&mut img);
+		img
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn composite() {
+		let particles = vec![
+			Particle::new(1.0, vec2(-0.5, 0.0), vec2(0.0, 0.0)),
+			Particle::new(0.0, vec2(0.5, 0.0), vec2(0.0, 0.0)),
+			Particle::new(0.0, vec2(0.5, 0.5), vec2(0.0, 0.0)),
+			Particle::new(0.0, vec2(-0.5, 0.5), vec2(0.0, 0.0)),
+		];
+		let asteroid = Meta::new().with_tag("asteroid");
+		let meta = vec![Meta::new().with_name("sun"), asteroid.clone(), asteroid, Meta::new()];
+		let mut c = Composite::new(4, 4, Viewport::from_scale(1.0))
+			.with_layer(Layer::new(Selection::name(&meta, "sun")).with_color([1.0, 1.0, 0.0]))
+			.with_layer(Layer::new(Selection::tag(&meta, "asteroid")).with_color([0.0, 0.0, 1.0]).with_weight(0.5))
+			.with_layer(Layer::new(Selection::range(3..4)).with_color([1.0, 0.0, 0.0]))
+			.with_layer(Layer::new(Selection::predicate(|p| p.pos.y > 0.0)).with_color([0.0, 1.0, 0.0]));
+		c.accumulate(&particles, 1.0);
+		c.accumulate(&particles, 1.0);
+		assert_eq!(c.layers()[1].density().pixels().iter().sum::<f32>(), 4.0);
+
+		let img = c.image();
+		assert_eq!(img.get_pixel(1, 2).0, [255, 255, 0, 255]); // sun
+		assert_eq!(img.get_pixel(3, 2).0, [0, 0, 127, 255]); // asteroid
+		assert_eq!(img.get_pixel(3, 3).0, [0, 255, 127, 255]); // asteroid, y > 0
+		assert_eq!(img.get_pixel(1, 3).0, [255, 255, 0, 255]); // index 3, y > 0
+		assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 255]);
+
+		c.clear();
+		assert_eq!(c.image().get_pixel(1, 2).0, [0, 0, 0, 255]);
+	}
+}
@@ -0,0 +1,181 @@
+use super::prelude::*;
+use std::io::Write;
+
+/// Conserved quantities of a particle system:
+/// energy, linear momentum and angular momentum.
+///
+/// In the absence of integration errors, these stay constant over time.
+/// Their drift is therefore a measure for the accuracy of a run.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Diagnostics {
+	pub kinetic_energy: f64,
+	pub potential_energy: f64,
+	pub momentum: vec2,
+	pub angular_momentum: f64,
+
+	// Sums of magnitudes, used to normalize the drift of quantities
+	// that are (nearly) zero by themselves (e.g. momentum after remove_net_momentum).
+	momentum_scale: f64,
+	angular_momentum_scale: f64,
+}
+
+/// Relative change of conserved quantities with respect to a reference state.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Drift {
+	pub energy: f64,
+	pub momentum: f64,
+	pub angular_momentum: f64,
+}
+
+impl Diagnostics {
+	/// Diagnostics of particles interacting via `force`.
+	/// The potential energy is consistent with the force model,
+	/// e.g. interactions omitted by PartialForce do not count.
+	pub fn new<F: Forces>(particles: &[Particle], force: &F) -> Self {
+		let mut kinetic_energy = 0.0;
+		let mut momentum = vec2::ZERO;
+		let mut angular_momentum = 0.0;
+		let mut momentum_scale = 0.0;
+		let mut angular_momentum_scale = 0.0;
+
+		for p in particles {
+			let mv = p.mass * p.vel;
+			kinetic_energy += 0.5 * mv.dot(p.vel);
+			momentum += mv;
+			angular_momentum += p.pos.cross(mv);
+			momentum_scale += mv.len();
+			angular_momentum_scale += f64::abs(p.pos.cross(mv));
+		}
+
+		Self {
+			kinetic_energy,
+			potential_energy: force.potential_energy(particles),
+			momentum,
+			angular_momentum,
+			momentum_scale,
+			angular_momentum_scale,
+		}
+	}
+
+	/// Total (kinetic + potential) energy.
+	pub fn energy(&self) -> f64 {
+		self.kinetic_energy + self.potential_energy
+	}
+
+	/// Relative drift with respect to an earlier (typically initial) state.
+	///
+	/// Energy and angular momentum are relative to their initial magnitude.
+	/// Momentum is relative to the initial sum of the particles' momentum magnitudes,
+	/// as the net momentum is typically zero.
+	pub fn drift(&self, initial: &Diagnostics) -> Drift {
+		Drift {
+			energy: relative(self.energy() - initial.energy(), f64::abs(initial.energy())),
+			momentum: relative((self.momentum - initial.momentum).len(), initial.momentum_scale),
+			angular_momentum: relative(self.angular_momentum - initial.angular_momentum, initial.angular_momentum_scale),
+		}
+	}
+}
+
+// delta / scale, but the absolute delta if the scale is zero
+// (e.g. momentum of a system where nothing moves).
+fn relative(delta: f64, scale: f64) -> f64 {
+	if scale == 0.0 {
+		delta
+	} else {
+		delta / scale
+	}
+}
+
+/// Writes diagnostics and their drift with respect to the first written state
+/// to a text file, one line per call to `write`.
+pub struct DiagnosticsFile {
+	out: OutputFile,
+	initial: Option<Diagnostics>,
+}
+
+impl DiagnosticsFile {
+	pub const FILE: &'static str = "diagnostics.txt";
+
+	pub fn new(mut out: OutputFile) -> Result<Self> {
+		out.header("# time energy kinetic_energy potential_energy momentum_x momentum_y angular_momentum energy_drift momentum_drift angular_momentum_drift")?;
+		Ok(Self { out, initial: None })
+	}
+
+	pub fn write(&mut self, time: f64, d: &Diagnostics) -> Result<()> {
+		let initial = self.initial.get_or_insert_with(|| d.clone());
+		let drift = d.drift(initial);
+		writeln!(
+			self.out,
+			"{}\t{}\t{}\t{}\t{}\t{}\t{}\t{:e}\t{:e}\t{:e}",
+			time,
+			d.energy(),
+			d.kinetic_energy,
+			d.potential_energy,
+			d.momentum.x,
+			d.momentum.y,
+			d.angular_momentum,
+			drift.energy,
+			drift.momentum,
+			drift.angular_momentum
+		)?;
+		self.out.flush()?;
+		Ok(())
+	}
+}
+
+impl<F: Forces> OutputSink<F> for DiagnosticsFile {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		self.write(sim.time(), &sim.diagnostics())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// two identical particles orbiting their centre of mass (0, 0)
+	// with unit distance, 2 pi period. (As in inputs/twobody.csv).
+	fn twobody() -> Vec<Particle> {
+		vec![
+			Particle::new(4.0, vec2(1.0, 0.0), vec2(0.0, 1.0)), //
+			Particle::new(4.0, vec2(-1.0, 0.0), vec2(0.0, -1.0)),
+		]
+	}
+
+	#[test]
+	fn twobody_diagnostics() {
+		let d = Diagnostics::new(&twobody(), &BruteForce::new());
+		assert_eq!(d.kinetic_energy, 4.0);
+		assert_eq!(d.potential_energy, -8.0);
+		assert_eq!(d.energy(), -4.0);
+		assert_eq!(d.momentum, vec2(0.0, 0.0));
+		assert_eq!(d.angular_momentum, 8.0);
+
+		let drift = d.drift(&d);
+		assert_eq!(
+			drift,
+			Drift {
+				energy: 0.0,
+				momentum: 0.0,
+				angular_momentum: 0.0
+			}
+		);
+		// nothing to compare to: absolute drift.
+		assert_eq!(relative(0.5, 0.0), 0.5);
+	}
+
+	// Leapfrog conserves momentum and angular momentum up to round-off,
+	// energy up to a small oscillating error.
+	#[test]
+	fn twobody_conservation() {
+		let mut sim = Stepper::new(twobody(), BruteForce::new());
+		let initial = sim.diagnostics();
+		sim.fix_dt(1e-3);
+		sim.advance(2.0 * PI);
+		let drift = sim.diagnostics().drift(&initial);
+
+		assert!(drift.energy.abs() < 1e-6, "energy drift {}", drift.energy);
+		assert!(drift.momentum.abs() < 1e-12, "momentum drift {}", drift.momentum);
+		assert!(drift.angular_momentum.abs() < 1e-12, "angular momentum drift {}", drift.angular_momentum);
+	}
+}
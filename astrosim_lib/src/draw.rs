@@ -0,0 +1,331 @@
+use super::prelude::*;
+
+/// Something to draw on: an `Image`, or an `image::RgbaImage` (e.g. a tone-mapped frame).
+///
+/// Shapes take continuous pixel coordinates (see `Viewport::to_pixel`),
+/// where pixel (i, j) covers [i, i+1) x [j, j+1). Anything outside the canvas is clipped.
+pub trait Canvas {
+	type Pixel: Copy;
+
+	/// Width and height in pixels.
+	fn size(&self) -> (i32, i32);
+
+	/// Set the pixel at (x, y), if inside the canvas.
+	fn put(&mut self, x: i32, y: i32, c: Self::Pixel);
+
+	/// Blend `c` over the pixel at (x, y) with opacity `alpha` (0..1), if inside the canvas.
+	/// Canvases without blending set the pixel if it is at least half opaque.
+	fn blend(&mut self, x: i32, y: i32, c: Self::Pixel, alpha: f32) {
+		if alpha >= 0.5 {
+			self.put(x, y, c);
+		}
+	}
+
+	/// Straight line from `a` to `b`, one pixel wide.
+	fn draw_line(&mut self, a: vec2, b: vec2, c: Self::Pixel) {
+		let (w, h) = self.size();
+		let (a, b) = match clip(a, b, vec2(0.0, 0.0), vec2(w as f64, h as f64)) {
+			Some(ab) => ab,
+			None => return,
+		};
+		let (x0, y0) = (a.x.floor() as i32, a.y.floor() as i32);
+		let (x1, y1) = (b.x.floor() as i32, b.y.floor() as i32);
+		// Bresenham
+		let (dx, dy) = ((x1 - x0).abs(), -(y1 - y0).abs());
+		let (sx, sy) = ((x1 - x0).signum(), (y1 - y0).signum());
+		let (mut x, mut y, mut err) = (x0, y0, dx + dy);
+		loop {
+			self.put(x, y, c);
+			if x == x1 && y == y1 {
+				break;
+			}
+			let e2 = 2 * err;
+			if e2 >= dy {
+				err += dy;
+				x += sx;
+			}
+			if e2 <= dx {
+				err += dx;
+				y += sy;
+			}
+		}
+	}
+
+	/// Anti-aliased straight line from `a` to `b` (Xiaolin Wu), blended with opacity `alpha` (0..1).
+	fn draw_line_aa(&mut self, a: vec2, b: vec2, c: Self::Pixel, alpha: f32) {
+		// (with a margin for the pixels next to the edges, which the line partly covers)
+		let (w, h) = self.size();
+		let (a, b) = match clip(a, b, vec2(-1.0, -1.0), vec2(w as f64 + 1.0, h as f64 + 1.0)) {
+			Some(ab) => ab,
+			None => return,
+		};
+		// Work in coordinates where pixel centers are integers, stepping along the major axis.
+		let (mut a, mut b) = (a - vec2(0.5, 0.5), b - vec2(0.5, 0.5));
+		let steep = (b.y - a.y).abs() > (b.x - a.x).abs();
+		if steep {
+			a = vec2(a.y, a.x);
+			b = vec2(b.y, b.x);
+		}
+		if a.x > b.x {
+			std::mem::swap(&mut a, &mut b);
+		}
+		let gradient = if b.x == a.x { 0.0 } else { (b.y - a.y) / (b.x - a.x) };
+		let (x0, x1) = (a.x.round() as i32, b.x.round() as i32);
+		for x in x0..=x1 {
+			let y = a.y + gradient * (x as f64 - a.x);
+			let (y0, f) = (y.floor(), y - y.floor());
+			for (y, coverage) in [(y0 as i32, 1.0 - f), (y0 as i32 + 1, f)] {
+				let (px, py) = if steep { (y, x) } else { (x, y) };
+				self.blend(px, py, c, alpha * coverage as f32);
+			}
+		}
+	}
+
+	/// Circle outline around `center`, one pixel wide.
+	fn draw_circle(&mut self, center: vec2, radius: f64, c: Self::Pixel) {
+		// enough segments for neighbouring points to be at most ~1 pixel apart.
+		let n = usize::clamp((2.0 * PI * radius) as usize, 8, 100_000);
+		let point = |i: usize| {
+			let theta = 2.0 * PI * (i as f64) / (n as f64);
+			center + radius * vec2(f64::cos(theta), f64::sin(theta))
+		};
+		for i in 0..n {
+			self.draw_line(point(i), point(i + 1), c);
+		}
+	}
+
+	/// Crosshair (+) centered on `center`, with arms `size` pixels long,
+	/// leaving a gap of `gap` pixels around the center so that it does not hide what it marks.
+	fn draw_crosshair(&mut self, center: vec2, size: f64, gap: f64, c: Self::Pixel) {
+		for &dir in &[vec2(1.0, 0.0), vec2(-1.0, 0.0), vec2(0.0, 1.0), vec2(0.0, -1.0)] {
+			self.draw_line(center + gap * dir, center + (gap + size) * dir, c);
+		}
+	}
+
+	/// Text with the top-left corner at pixel (x, y), in a 5x7 pixel font magnified `scale` times.
+	/// Newlines start a new line.
+	fn draw_text(&mut self, x: i32, y: i32, text: &str, scale: u32, c: Self::Pixel) {
+		let s = scale as i32;
+		let (mut cx, mut cy) = (x, y);
+		for ch in text.chars() {
+			if ch == '\n' {
+				cx = x;
+				cy += LINE_HEIGHT * s;
+				continue;
+			}
+			for (col, bits) in glyph(ch).iter().enumerate() {
+				for row in 0..8 {
+					if bits & (1 << row) != 0 {
+						for dy in 0..s {
+							for dx in 0..s {
+								self.put(cx + (col as i32) * s + dx, cy + row * s + dy, c);
+							}
+						}
+					}
+				}
+			}
+			cx += CHAR_WIDTH * s;
+		}
+	}
+}
+
+/// Horizontal advance per character of `draw_text`, in pixels (at scale 1).
+pub const CHAR_WIDTH: i32 = 6;
+
+/// Vertical advance per line of `draw_text`, in pixels (at scale 1).
+pub const LINE_HEIGHT: i32 = 10;
+
+/// Width and height of `text` as drawn by `draw_text`.
+pub fn text_size(text: &str, scale: u32) -> (i32, i32) {
+	let cols = text.lines().map(|l| l.chars().count()).max().unwrap_or(0) as i32;
+	let rows = text.lines().count() as i32;
+	(cols * CHAR_WIDTH * scale as i32, rows * LINE_HEIGHT * scale as i32)
+}
+
+// Clip segment a-b to the rectangle from `min` to `max` (Cohen-Sutherland),
+// so that lines far outside the canvas cost nothing. None if nothing is inside (or not finite).
+fn clip(mut a: vec2, mut b: vec2, min: vec2, max: vec2) -> Option<(vec2, vec2)> {
+	if !(a.is_finite() && b.is_finite()) {
+		return None;
+	}
+	let code = |p: vec2| (p.x < min.x) as u8 | ((p.x > max.x) as u8) << 1 | ((p.y < min.y) as u8) << 2 | ((p.y > max.y) as u8) << 3;
+	let (mut ca, mut cb) = (code(a), code(b));
+	loop {
+		if ca | cb == 0 {
+			return Some((a, b));
+		}
+		if ca & cb != 0 {
+			return None;
+		}
+		// Move an outside end point onto the edge it is beyond.
+		let c = if ca != 0 { ca } else { cb };
+		let d = b - a;
+		let p = if c & 1 != 0 {
+			vec2(min.x, a.y + d.y * (min.x - a.x) / d.x)
+		} else if c & 2 != 0 {
+			vec2(max.x, a.y + d.y * (max.x - a.x) / d.x)
+		} else if c & 4 != 0 {
+			vec2(a.x + d.x * (min.y - a.y) / d.y, min.y)
+		} else {
+			vec2(a.x + d.x * (max.y - a.y) / d.y, max.y)
+		};
+		if c == ca {
+			a = p;
+			ca = code(a);
+		} else {
+			b = p;
+			cb = code(b);
+		}
+	}
+}
+
+impl<C: Copy + Default> Canvas for Image<C> {
+	type Pixel = C;
+
+	fn size(&self) -> (i32, i32) {
+		self.dimensions()
+	}
+
+	fn put(&mut self, x: i32, y: i32, c: C) {
+		let (w, h) = self.dimensions();
+		if x >= 0 && x < w && y >= 0 && y < h {
+			self[y as usize][x as usize] = c;
+		}
+	}
+}
+
+impl Canvas for image::RgbaImage {
+	type Pixel = image::Rgba<u8>;
+
+	fn size(&self) -> (i32, i32) {
+		(self.width() as i32, self.height() as i32)
+	}
+
+	fn put(&mut self, x: i32, y: i32, c: image::Rgba<u8>) {
+		if x >= 0 && x < self.width() as i32 && y >= 0 && y < self.height() as i32 {
+			self.put_pixel(x as u32, y as u32, c);
+		}
+	}
+
+	// The colour's own alpha scales `alpha`.
+	fn blend(&mut self, x: i32, y: i32, c: image::Rgba<u8>, alpha: f32) {
+		let alpha = alpha * c.0[3] as f32 / 255.0;
+		if x < 0 || x >= self.width() as i32 || y < 0 || y >= self.height() as i32 || alpha.is_nan() || alpha <= 0.0 {
+			return;
+		}
+		let alpha = alpha.min(1.0);
+		let p = self.get_pixel_mut(x as u32, y as u32);
+		for (old, &new) in p.0[..3].iter_mut().zip(&c.0[..3]) {
+			*old = (*old as f32 * (1.0 - alpha) + new as f32 * alpha).round() as u8;
+		}
+		p.0[3] = (p.0[3] as f32 * (1.0 - alpha) + 255.0 * alpha).round() as u8;
+	}
+}
+
+// Columns of a 5x7 glyph (bit 0 is the top row, bit 7 a descender).
+// Characters outside printable ASCII are drawn as '?'.
+fn glyph(c: char) -> &'static [u8; 5] {
+	let i = c as usize;
+	if (0x20..0x7f).contains(&i) {
+		&FONT[i - 0x20]
+	} else {
+		&FONT[(b'?' - 0x20) as usize]
+	}
+}
+
+// The classic 5x7 LCD font, for ASCII 0x20 (space) to 0x7e (~).
+#[rustfmt::skip]
+const FONT: [[u8; 5]; 95] = [
+	[0x00, 0x00, 0x00, 0x00, 0x00], [0x00, 0x00, 0x5f, 0x00, 0x00], [0x00, 0x07, 0x00, 0x07, 0x00], [0x14, 0x7f, 0x14, 0x7f, 0x14], //  !"#
+	[0x24, 0x2a, 0x7f, 0x2a, 0x12], [0x23, 0x13, 0x08, 0x64, 0x62], [0x36, 0x49, 0x56, 0x20, 0x50], [0x00, 0x08, 0x07, 0x03, 0x00], // $%&'
+	[0x00, 0x1c, 0x22, 0x41, 0x00], [0x00, 0x41, 0x22, 0x1c, 0x00], [0x2a, 0x1c, 0x7f, 0x1c, 0x2a], [0x08, 0x08, 0x3e, 0x08, 0x08], // ()*+
+	[0x00, 0x80, 0x70, 0x30, 0x00], [0x08, 0x08, 0x08, 0x08, 0x08], [0x00, 0x00, 0x60, 0x60, 0x00], [0x20, 0x10, 0x08, 0x04, 0x02], // ,-./
+	[0x3e, 0x51, 0x49, 0x45, 0x3e], [0x00, 0x42, 0x7f, 0x40, 0x00], [0x72, 0x49, 0x49, 0x49, 0x46], [0x21, 0x41, 0x49, 0x4d, 0x33], // 0123
+	[0x18, 0x14, 0x12, 0x7f, 0x10], [0x27, 0x45, 0x45, 0x45, 0x39], [0x3c, 0x4a, 0x49, 0x49, 0x31], [0x41, 0x21, 0x11, 0x09, 0x07], // 4567
+	[0x36, 0x49, 0x49, 0x49, 0x36], [0x46, 0x49, 0x49, 0x29, 0x1e], [0x00, 0x00, 0x14, 0x00, 0x00], [0x00, 0x40, 0x34, 0x00, 0x00], // 89:;
+	[0x00, 0x08, 0x14, 0x22, 0x41], [0x14, 0x14, 0x14, 0x14, 0x14], [0x00, 0x41, 0x22, 0x14, 0x08], [0x02, 0x01, 0x59, 0x09, 0x06], // <=>?
+	[0x3e, 0x41, 0x5d, 0x59, 0x4e], [0x7c, 0x12, 0x11, 0x12, 0x7c], [0x7f, 0x49, 0x49, 0x49, 0x36], [0x3e, 0x41, 0x41, 0x41, 0x22], // @ABC
+	[0x7f, 0x41, 0x41, 0x41, 0x3e], [0x7f, 0x49, 0x49, 0x49, 0x41], [0x7f, 0x09, 0x09, 0x09, 0x01], [0x3e, 0x41, 0x41, 0x51, 0x73], // DEFG
+	[0x7f, 0x08, 0x08, 0x08, 0x7f], [0x00, 0x41, 0x7f, 0x41, 0x00], [0x20, 0x40, 0x41, 0x3f, 0x01], [0x7f, 0x08, 0x14, 0x22, 0x41], // HIJK
+	[0x7f, 0x40, 0x40, 0x40, 0x40], [0x7f, 0x02, 0x1c, 0x02, 0x7f], [0x7f, 0x04, 0x08, 0x10, 0x7f], [0x3e, 0x41, 0x41, 0x41, 0x3e], // LMNO
+	[0x7f, 0x09, 0x09, 0x09, 0x06], [0x3e, 0x41, 0x51, 0x21, 0x5e], [0x7f, 0x09, 0x19, 0x29, 0x46], [0x26, 0x49, 0x49, 0x49, 0x32], // PQRS
+	[0x03, 0x01, 0x7f, 0x01, 0x03], [0x3f, 0x40, 0x40, 0x40, 0x3f], [0x1f, 0x20, 0x40, 0x20, 0x1f], [0x3f, 0x40, 0x38, 0x40, 0x3f], // TUVW
+	[0x63, 0x14, 0x08, 0x14, 0x63], [0x03, 0x04, 0x78, 0x04, 0x03], [0x61, 0x59, 0x49, 0x4d, 0x43], [0x00, 0x7f, 0x41, 0x41, 0x41], // XYZ[
+	[0x02, 0x04, 0x08, 0x10, 0x20], [0x00, 0x41, 0x41, 0x41, 0x7f], [0x04, 0x02, 0x01, 0x02, 0x04], [0x40, 0x40, 0x40, 0x40, 0x40], // \]^_
+	[0x00, 0x03, 0x07, 0x08, 0x00], [0x20, 0x54, 0x54, 0x78, 0x40], [0x7f, 0x28, 0x44, 0x44, 0x38], [0x38, 0x44, 0x44, 0x44, 0x28], // `abc
+	[0x38, 0x44, 0x44, 0x28, 0x7f], [0x38, 0x54, 0x54, 0x54, 0x18], [0x00, 0x08, 0x7e, 0x09, 0x02], [0x18, 0xa4, 0xa4, 0x9c, 0x78], // defg
+	[0x7f, 0x08, 0x04, 0x04, 0x78], [0x00, 0x44, 0x7d, 0x40, 0x00], [0x20, 0x40, 0x40, 0x3d, 0x00], [0x7f, 0x10, 0x28, 0x44, 0x00], // hijk
+	[0x00, 0x41, 0x7f, 0x40, 0x00], [0x7c, 0x04, 0x78, 0x04, 0x78], [0x7c, 0x08, 0x04, 0x04, 0x78], [0x38, 0x44, 0x44, 0x44, 0x38], // lmno
+	[0xfc, 0x18, 0x24, 0x24, 0x18], [0x18, 0x24, 0x24, 0x18, 0xfc], [0x7c, 0x08, 0x04, 0x04, 0x08], [0x48, 0x54, 0x54, 0x54, 0x24], // pqrs
+	[0x04, 0x04, 0x3f, 0x44, 0x24], [0x3c, 0x40, 0x40, 0x20, 0x7c], [0x1c, 0x20, 0x40, 0x20, 0x1c], [0x3c, 0x40, 0x30, 0x40, 0x3c], // tuvw
+	[0x44, 0x28, 0x10, 0x28, 0x44], [0x4c, 0x90, 0x90, 0x90, 0x7c], [0x44, 0x64, 0x54, 0x4c, 0x44], [0x00, 0x08, 0x36, 0x41, 0x00], // xyz{
+	[0x00, 0x00, 0x77, 0x00, 0x00], [0x00, 0x41, 0x36, 0x08, 0x00], [0x02, 0x01, 0x02, 0x04, 0x02], // |}~
+];
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	// Rows of the image as strings, '#' for set pixels.
+	fn rows(img: &Image<u8>) -> Vec<String> {
+		(0..img.height()).map(|y| img[y].iter().map(|&v| if v != 0 { '#' } else { '.' }).collect()).collect()
+	}
+
+	#[test]
+	fn text() {
+		let mut img = Image::<u8>::new(12, 8);
+		img.draw_text(0, 0, "t1", 1, 1);
+		#[rustfmt::skip]
+		assert_eq!(rows(&img), vec![
+			"..#.....#...",
+			"..#....##...",
+			"#####...#...",
+			"..#.....#...",
+			"..#.....#...",
+			"..#.#...#...",
+			"...#...###..",
+			"............",
+		]);
+		assert_eq!(text_size("t = 1\nN = 20", 2), (6 * 6 * 2, 2 * 10 * 2));
+	}
+
+	#[test]
+	fn shapes() {
+		let mut img = Image::<u8>::new(7, 7);
+		img.draw_line(vec2(0.5, 0.5), vec2(6.5, 3.5), 1);
+		assert_eq!(rows(&img)[..4], ["#......", ".##....", "...##..", ".....##"]);
+
+		let mut img = Image::<u8>::new(7, 7);
+		img.draw_crosshair(vec2(3.5, 3.5), 2.0, 1.0, 1);
+		assert_eq!(rows(&img), ["...#...", "...#...", "...#...", "###.###", "...#...", "...#...", "...#..."]);
+
+		let mut img = Image::<u8>::new(9, 9);
+		img.draw_circle(vec2(4.5, 4.5), 3.0, 1);
+		let r = rows(&img);
+		assert_eq!(r[0], ".........");
+		assert_eq!(r[4], ".#.....#.");
+		assert_eq!(r[7], "...####..");
+
+		// clipped, no panic.
+		let mut img = Image::<u8>::new(4, 4);
+		img.draw_line(vec2(-10.0, -10.0), vec2(10.0, 10.0), 1);
+		img.draw_text(-3, 2, "hello", 3, 1);
+		assert_eq!(rows(&img), ["#...", ".#..", "..#.", "...#"]);
+		// far outside: no overflow, and no time spent stepping outside.
+		let mut img = Image::<u8>::new(4, 4);
+		img.draw_line(vec2(-1e12, 0.5), vec2(1e12, 0.5), 1);
+		img.draw_line(vec2(f64::NAN, 0.0), vec2(1.0, 1.0), 1);
+		img.draw_circle(vec2(0.0, 0.0), 1e9, 1);
+		assert_eq!(rows(&img)[0], "####");
+
+		// anti-aliased: through pixel centers, and halfway between two rows.
+		let mut img = image::RgbaImage::new(6, 4);
+		img.draw_line_aa(vec2(0.5, 1.5), vec2(5.5, 1.5), image::Rgba([255, 255, 255, 255]), 1.0);
+		img.draw_line_aa(vec2(2.0, 4.0), vec2(2.0, -1.0), image::Rgba([200, 0, 0, 255]), 0.5);
+		assert_eq!(img.get_pixel(0, 1).0, [255, 255, 255, 255]);
+		assert_eq!(img.get_pixel(0, 0).0, [0, 0, 0, 0]);
+		assert_eq!(img.get_pixel(1, 3).0, [50, 0, 0, 64]);
+		assert_eq!(img.get_pixel(2, 3).0, [50, 0, 0, 64]);
+		assert_eq!(img.get_pixel(1, 1).0, [241, 191, 191, 255]);
+	}
+}
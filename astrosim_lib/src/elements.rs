@@ -0,0 +1,334 @@
+use super::prelude::*;
+
+/// Osculating Keplerian elements of a 2D orbit around a primary body.
+///
+/// The shape of the orbit is given by the semi-latus rectum `p` and eccentricity `e`,
+/// so that elliptic (e < 1), parabolic (e == 1) and hyperbolic (e > 1) orbits
+/// are all represented. See `a()` for the semi-major axis.
+///
+/// Angles are in radians.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Elements {
+	/// Gravitational parameter G*(M+m) of primary and secondary (G = 1).
+	pub mu: f64,
+	/// Semi-latus rectum, p = a * (1 - e²).
+	pub p: f64,
+	/// Eccentricity.
+	pub e: f64,
+	/// Argument of periapsis: direction of periapsis, counter-clockwise from the X axis.
+	pub omega: f64,
+	/// True anomaly: angle from periapsis to the position, in the direction of motion.
+	pub nu: f64,
+	/// The orbit runs clockwise (negative angular momentum).
+	pub retrograde: bool,
+}
+
+impl Elements {
+	/// Elements of an elliptic (a > 0, e < 1) or hyperbolic (a < 0, e > 1) prograde orbit,
+	/// given the semi-major axis and mean anomaly.
+	/// Parabolic orbits have no finite semi-major axis, see `parabolic`.
+	pub fn new(mu: f64, a: f64, e: f64, omega: f64, mean_anomaly: f64) -> Self {
+		Self::with_mean_anomaly(mu, a * (1.0 - e * e), e, omega, mean_anomaly)
+	}
+
+	/// Elements of a prograde parabolic orbit with periapsis distance `q`.
+	pub fn parabolic(mu: f64, q: f64, omega: f64, mean_anomaly: f64) -> Self {
+		Self::with_mean_anomaly(mu, 2.0 * q, 1.0, omega, mean_anomaly)
+	}
+
+	fn with_mean_anomaly(mu: f64, p: f64, e: f64, omega: f64, mean_anomaly: f64) -> Self {
+		let mut el = Self {
+			mu,
+			p,
+			e,
+			omega,
+			nu: 0.0,
+			retrograde: false,
+		};
+		el.set_mean_anomaly(mean_anomaly);
+		el
+	}
+
+	/// Elements of an orbit with position `pos` and velocity `vel`
+	/// relative to a primary at the origin.
+	pub fn from_state(mu: f64, pos: vec2, vel: vec2) -> Self {
+		let h = pos.cross(vel);
+		let r = pos.len();
+		let e_vec = ((vel.len2() - mu / r) * pos - pos.dot(vel) * vel) / mu;
+		let e = e_vec.len();
+		let p = h * h / mu;
+		let retrograde = h < 0.0;
+
+		// Periapsis is undefined for circular orbits,
+		// by convention it is placed on the X axis.
+		let omega = if e == 0.0 { 0.0 } else { f64::atan2(e_vec.y, e_vec.x) };
+		let theta = f64::atan2(pos.y, pos.x);
+		let nu = if retrograde { omega - theta } else { theta - omega };
+
+		Self {
+			mu,
+			p,
+			e,
+			omega,
+			nu: wrap_angle(nu),
+			retrograde,
+		}
+	}
+
+	/// Elements of `particle`'s orbit around `primary`.
+	pub fn of(particle: &Particle, primary: &Particle) -> Self {
+		let mu = particle.mass + primary.mass;
+		Self::from_state(mu, particle.pos - primary.pos, particle.vel - primary.vel)
+	}
+
+	/// Position and velocity relative to the primary.
+	pub fn to_state(&self) -> (vec2, vec2) {
+		let sign = if self.retrograde { -1.0 } else { 1.0 };
+		let (sin_nu, cos_nu) = self.nu.sin_cos();
+		let theta = self.omega + sign * self.nu;
+		let radial = vec2(theta.cos(), theta.sin());
+		let tangential = vec2(-radial.y, radial.x);
+
+		let r = self.p / (1.0 + self.e * cos_nu);
+		let v = f64::sqrt(self.mu / self.p);
+		let pos = r * radial;
+		let vel = (v * self.e * sin_nu) * radial + (sign * v * (1.0 + self.e * cos_nu)) * tangential;
+		(pos, vel)
+	}
+
+	/// A particle with given mass on this orbit around `primary`.
+	/// `mu` is assumed to include the particle's mass.
+	pub fn to_particle(&self, mass: f64, primary: &Particle) -> Particle {
+		let (pos, vel) = self.to_state();
+		Particle::new(mass, primary.pos + pos, primary.vel + vel)
+	}
+
+	/// Semi-major axis. Negative for hyperbolic orbits, infinite for parabolic orbits.
+	pub fn a(&self) -> f64 {
+		if self.e == 1.0 {
+			INF
+		} else {
+			self.p / (1.0 - self.e * self.e)
+		}
+	}
+
+	/// Distance of closest approach.
+	pub fn periapsis(&self) -> f64 {
+		self.p / (1.0 + self.e)
+	}
+
+	/// Orbital period. Infinite for unbound (parabolic, hyperbolic) orbits.
+	pub fn period(&self) -> f64 {
+		if self.is_bound() {
+			2.0 * PI / self.mean_motion()
+		} else {
+			INF
+		}
+	}
+
+	/// Elliptic orbit (e < 1).
+	pub fn is_bound(&self) -> bool {
+		self.e < 1.0
+	}
+
+	/// Rate of change of the mean anomaly.
+	///
+	/// For parabolic orbits, this is 2*sqrt(mu/p³), so that the mean anomaly
+	/// equals Barker's D + D³/3 (D = tan(nu/2)).
+	pub fn mean_motion(&self) -> f64 {
+		if self.e == 1.0 {
+			2.0 * f64::sqrt(self.mu / self.p.powi(3))
+		} else {
+			f64::sqrt(self.mu / self.a().abs().powi(3))
+		}
+	}
+
+	/// Mean anomaly, proportional to the time since periapsis passage.
+	pub fn mean_anomaly(&self) -> f64 {
+		let e = self.e;
+		let nu = self.nu;
+		if e < 1.0 {
+			let ecc = f64::atan2(f64::sqrt(1.0 - e * e) * nu.sin(), e + nu.cos());
+			ecc - e * ecc.sin()
+		} else if e > 1.0 {
+			let hyp = 2.0 * f64::atanh(f64::sqrt((e - 1.0) / (e + 1.0)) * (nu / 2.0).tan());
+			e * hyp.sinh() - hyp
+		} else {
+			let d = (nu / 2.0).tan();
+			d + d * d * d / 3.0
+		}
+	}
+
+	/// Time since periapsis passage (negative before periapsis).
+	pub fn time_since_periapsis(&self) -> f64 {
+		self.mean_anomaly() / self.mean_motion()
+	}
+
+	/// n + 1 states (position, velocity) along the orbit, relative to the primary, evenly spaced in true anomaly:
+	/// the whole (closed) ellipse, or an unbound orbit out to (almost) its asymptotes.
+	/// Empty for degenerate (radial) orbits.
+	pub fn path(&self, n: usize) -> Vec<(vec2, vec2)> {
+		if self.p.is_nan() || self.p <= 0.0 {
+			return Vec::new();
+		}
+		let max_nu = if self.is_bound() { PI } else { 0.99 * f64::acos(-1.0 / self.e) };
+		let mut el = *self;
+		(0..=n)
+			.map(|i| {
+				el.nu = -max_nu + 2.0 * max_nu * (i as f64) / (n as f64);
+				el.to_state()
+			})
+			.collect()
+	}
+
+	/// Set the true anomaly by solving Kepler's equation for the given mean anomaly.
+	pub fn set_mean_anomaly(&mut self, mean_anomaly: f64) {
+		let e = self.e;
+		self.nu = if e < 1.0 {
+			let ecc = solve_kepler_elliptic(e, wrap_angle(mean_anomaly));
+			2.0 * f64::atan2(f64::sqrt(1.0 + e) * (ecc / 2.0).sin(), f64::sqrt(1.0 - e) * (ecc / 2.0).cos())
+		} else if e > 1.0 {
+			let hyp = solve_kepler_hyperbolic(e, mean_anomaly);
+			2.0 * f64::atan(f64::sqrt((e + 1.0) / (e - 1.0)) * (hyp / 2.0).tanh())
+		} else {
+			// Barker's equation has a closed-form solution.
+			let b = 1.5 * mean_anomaly;
+			let y = f64::cbrt(b + f64::sqrt(b * b + 1.0));
+			2.0 * f64::atan(y - 1.0 / y)
+		}
+	}
+}
+
+// Solve M = E - e sin(E) for the eccentric anomaly E (Newton-Raphson).
+fn solve_kepler_elliptic(e: f64, m: f64) -> f64 {
+	let mut ecc = if e < 0.8 { m } else { PI * m.signum() };
+	for _i in 0..100 {
+		let delta = (ecc - e * ecc.sin() - m) / (1.0 - e * ecc.cos());
+		ecc -= delta;
+		if delta.abs() < 1e-15 {
+			break;
+		}
+	}
+	ecc
+}
+
+// Solve M = e sinh(H) - H for the hyperbolic anomaly H (Newton-Raphson).
+fn solve_kepler_hyperbolic(e: f64, m: f64) -> f64 {
+	let mut hyp = f64::asinh(m / e);
+	for _i in 0..100 {
+		let delta = (e * hyp.sinh() - hyp - m) / (e * hyp.cosh() - 1.0);
+		hyp -= delta;
+		if delta.abs() < 1e-15 * f64::max(1.0, hyp.abs()) {
+			break;
+		}
+	}
+	hyp
+}
+
+// Wrap an angle to the interval [-pi, pi].
+fn wrap_angle(angle: f64) -> f64 {
+	angle - 2.0 * PI * f64::round(angle / (2.0 * PI))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn assert_close(got: f64, want: f64, tol: f64) {
+		if (got - want).abs() > tol {
+			panic!("got {}, want {} (tolerance {})", got, want, tol)
+		}
+	}
+
+	fn assert_vec_close(got: vec2, want: vec2, tol: f64) {
+		if (got - want).len() > tol {
+			panic!("got {}, want {} (tolerance {})", got, want, tol)
+		}
+	}
+
+	// state -> elements -> state must be the identity,
+	// for elliptic, parabolic and hyperbolic orbits in both directions.
+	#[test]
+	fn state_round_trip() {
+		let pos = vec2(0.3, -1.2);
+		for &v in &[0.1, 0.5, 0.9, f64::sqrt(2.0 / pos.len()), 1.5, 3.0] {
+			for &dir in &[vec2(0.2, 1.0), vec2(-1.0, -0.3), vec2(0.0, -1.0)] {
+				let vel = v * dir.normalized();
+				let el = Elements::from_state(1.0, pos, vel);
+				let (got_pos, got_vel) = el.to_state();
+				assert_vec_close(got_pos, pos, 1e-12);
+				assert_vec_close(got_vel, vel, 1e-12);
+			}
+		}
+	}
+
+	// elements -> state -> elements must be the identity.
+	#[test]
+	fn elements_round_trip() {
+		for &(a, e) in &[(1.0, 0.0), (5.2, 0.048), (1.0, 0.5), (2.0, 0.99), (-1.0, 1.5), (-0.1, 10.0)] {
+			for &m in &[-3.0, -1.0, 0.0, 0.5, 2.5] {
+				let want = Elements::new(2.0, a, e, 0.7, m);
+				let (pos, vel) = want.to_state();
+				let got = Elements::from_state(2.0, pos, vel);
+				assert_close(got.a(), a, 1e-9 * a.abs());
+				assert_close(got.e, e, 1e-9);
+				if e != 0.0 {
+					assert_close(got.omega, 0.7, 1e-9);
+					assert_close(got.mean_anomaly(), m, 1e-9);
+				} else {
+					// circular: only the sum of omega and mean anomaly is defined.
+					assert_close(wrap_angle(got.omega + got.mean_anomaly() - 0.7 - m), 0.0, 1e-9);
+				}
+				assert!(!got.retrograde);
+			}
+		}
+	}
+
+	#[test]
+	fn parabolic_round_trip() {
+		for &m in &[-10.0, -0.3, 0.0, 1.0, 4.0] {
+			let el = Elements::parabolic(1.0, 0.5, -2.0, m);
+			assert_close(el.mean_anomaly(), m, 1e-12 * f64::max(1.0, m.abs()));
+			let (pos, vel) = el.to_state();
+			assert_close(vel.len2() / 2.0 - 1.0 / pos.len(), 0.0, 1e-12); // zero energy
+			assert_close(Elements::from_state(1.0, pos, vel).periapsis(), 0.5, 1e-12);
+		}
+	}
+
+	#[test]
+	fn retrograde() {
+		let el = Elements::from_state(1.0, vec2(1.0, 0.0), vec2(0.0, -1.2));
+		assert!(el.retrograde);
+		assert_close(el.omega, 0.0, 1e-12);
+		assert_close(el.nu, 0.0, 1e-12);
+
+		let (pos, vel) = el.to_state();
+		assert_vec_close(pos, vec2(1.0, 0.0), 1e-12);
+		assert_vec_close(vel, vec2(0.0, -1.2), 1e-12);
+	}
+
+	// Unit circular orbit, as in inputs/onebody.csv.
+	#[test]
+	fn circular() {
+		let sun = Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0));
+		let earth = Particle::new(0.0, vec2(0.0, 1.0), vec2(-1.0, 0.0));
+		let el = Elements::of(&earth, &sun);
+		assert_close(el.a(), 1.0, 1e-15);
+		assert_close(el.e, 0.0, 1e-15);
+		assert_close(el.period(), 2.0 * PI, 1e-15);
+		assert_close(el.nu, PI / 2.0, 1e-15);
+	}
+
+	// The mean anomaly must advance uniformly with simulated time.
+	#[test]
+	fn kepler_equation() {
+		let el = Elements::new(1.0, 1.0, 0.6, 0.3, 0.0);
+		let sun = Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0));
+		let mut sim = Stepper::new(vec![sun.clone(), el.to_particle(0.0, &sun)], BruteForce::new());
+		sim.fix_dt(1e-4);
+		sim.advance(1.0);
+		let got = Elements::of(&sim.particles()[1], &sim.particles()[0]);
+		assert_close(got.mean_anomaly(), 1.0, 1e-6);
+		assert_close(got.time_since_periapsis(), 1.0, 1e-6);
+	}
+}
@@ -0,0 +1,92 @@
+/// Time-weighting of snapshots for time-integrated quantities (density images, histograms, ...),
+/// so that they do not depend on the (adaptive) time step history.
+///
+/// Each step's snapshot is weighted by the time actually elapsed, which `Stepper::dt` is not
+/// (e.g. after a step truncated to end at an output time).
+///
+///   let mut exposure = Exposure::new();
+///   sim.advance_with_callback(1.0, |s| {
+///       if let Some(weight) = exposure.add(s.time(), s.next_dt()) {
+///           accumulate_density(&mut img, s.particles().iter(), &viewport, weight);
+///       }
+///       Ok(())
+///   })?;
+///
+#[derive(Clone, Debug, Default)]
+pub struct Exposure {
+	trapezoid: bool,
+	// Time of the previous snapshot, None before the first.
+	time: Option<f64>,
+	// Trapezoidal rule: the weight given to the previous snapshot for the interval after it.
+	forward: f32,
+}
+
+impl Exposure {
+	/// Weight each snapshot by the interval since the previous one (rectangle rule).
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Weight each snapshot by half the intervals before and after it (trapezoidal rule),
+	/// interpolating linearly between steps.
+	/// The interval after it is the next step size passed to `add`, see `Stepper::next_dt`,
+	/// so a snapshot at an output time is accumulated whole, before the output.
+	pub fn with_trapezoid(mut self, trapezoid: bool) -> Self {
+		self.trapezoid = trapezoid;
+		self
+	}
+
+	/// Add the snapshot at `time`, followed by a step of `next_dt` (0 if unknown, e.g. at the end).
+	/// Returns the weight to accumulate the snapshot with, if any.
+	/// With the rectangle rule, the first snapshot only starts the exposure.
+	pub fn add(&mut self, time: f64, next_dt: f64) -> Option<f32> {
+		let dt = match self.time.replace(time) {
+			Some(prev) => (time - prev) as f32,
+			None => 0.0,
+		};
+		let weight = if self.trapezoid {
+			// Whatever of the interval before was not given to the previous snapshot
+			// (all of it with `next_dt` 0, half of it otherwise), and half the interval after.
+			let forward = 0.5 * next_dt as f32;
+			dt - std::mem::replace(&mut self.forward, forward) + forward
+		} else {
+			dt
+		};
+		if weight > 0.0 {
+			Some(weight)
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn exposure() {
+		let times = [0.0, 0.5, 1.5, 1.75, 3.0];
+		// the next step size is known, except where `unknown`.
+		let weights = |mut exposure: Exposure, unknown: usize| {
+			let mut weights = Vec::new();
+			for (i, &t) in times.iter().enumerate() {
+				let next_dt = if i == unknown { 0.0 } else { times.get(i + 1).map_or(0.0, |next| next - t) };
+				weights.push(exposure.add(t, next_dt).unwrap_or(0.0));
+			}
+			weights
+		};
+
+		// elapsed intervals, not the next step.
+		assert_eq!(weights(Exposure::new(), 9), vec![0.0, 0.5, 1.0, 0.25, 1.25]);
+
+		// (t[i+1] - t[i-1]) / 2, half intervals at the ends.
+		let trapezoid = weights(Exposure::new().with_trapezoid(true), 9);
+		assert_eq!(trapezoid, vec![0.25, 0.75, 0.625, 0.75, 0.625]);
+
+		// without the next step (e.g. at the end of an advance), the whole interval goes to the next snapshot.
+		let split = weights(Exposure::new().with_trapezoid(true), 2);
+		assert_eq!(split, vec![0.25, 0.75, 0.5, 0.875, 0.625]);
+		assert_eq!(split.iter().sum::<f32>(), 3.0);
+	}
+}
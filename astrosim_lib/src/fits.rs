@@ -0,0 +1,205 @@
+use super::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::Path;
+
+/// FITS files consist of blocks of this many bytes.
+const BLOCK: usize = 2880;
+
+/// Header cards are lines of this many characters.
+const CARD: usize = 80;
+
+/// Value of a FITS header keyword.
+#[derive(Clone, Debug, PartialEq)]
+pub enum FitsValue {
+	Bool(bool),
+	Int(i64),
+	Float(f64),
+	Str(String),
+}
+
+/// Header keywords of a FITS image, besides the mandatory ones (SIMPLE, BITPIX, NAXIS...)
+/// which `save_fits` writes itself.
+///
+///   let header = FitsHeader::new().with_time(sim.time()).with_viewport(&viewport, w, h).with("OBJECT", "kirkwood", "");
+///   save_fits(&density, &header, "density.fits")?;
+///
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct FitsHeader {
+	cards: Vec<(String, FitsValue, String)>,
+}
+
+impl FitsHeader {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Add keyword `key` (at most 8 characters, upper case) with a value and (possibly empty) comment.
+	pub fn with<V: Into<FitsValue>>(mut self, key: &str, value: V, comment: &str) -> Self {
+		assert!(
+			key.len() <= 8 && key.chars().all(|c| c.is_ascii_uppercase() || c.is_ascii_digit() || c == '-' || c == '_'),
+			"invalid FITS keyword {:?}",
+			key
+		);
+		self.cards.push((key.into(), value.into(), comment.into()));
+		self
+	}
+
+	/// Simulation time of the image (TIME).
+	pub fn with_time(self, time: f64) -> Self {
+		self.with("TIME", time, "simulation time")
+	}
+
+	/// Linear world coordinates (WCS) of a w x h image of `viewport`:
+	/// FITS pixel coordinates map to simulation x, y including the viewport's rotation.
+	pub fn with_viewport(self, viewport: &Viewport, w: usize, h: usize) -> Self {
+		let (sx, sy) = (viewport.width / w as f64, viewport.height / h as f64);
+		let (sin, cos) = viewport.rotation.sin_cos();
+		// FITS pixel n (1-based) is centered on our continuous pixel coordinate n - 0.5,
+		// the viewport center on w/2 + 0.5 (see `Viewport`).
+		self.with("CTYPE1", "X", "simulation x")
+			.with("CTYPE2", "Y", "simulation y")
+			.with("CRPIX1", 0.5 * w as f64 + 1.0, "viewport center, pixels")
+			.with("CRPIX2", 0.5 * h as f64 + 1.0, "viewport center, pixels")
+			.with("CRVAL1", viewport.center.x, "viewport center")
+			.with("CRVAL2", viewport.center.y, "viewport center")
+			.with("CD1_1", cos * sx, "world units per pixel")
+			.with("CD1_2", -sin * sy, "")
+			.with("CD2_1", sin * sx, "")
+			.with("CD2_2", cos * sy, "")
+	}
+
+	/// Value of keyword `key`, if present.
+	pub fn get(&self, key: &str) -> Option<&FitsValue> {
+		self.cards.iter().find(|(k, _, _)| k == key).map(|(_, v, _)| v)
+	}
+}
+
+/// Save as a single-image FITS file of 32-bit floats, with the header keywords added.
+/// The first image row (y = 0) is the first FITS row, which FITS viewers show at the bottom.
+pub fn save_fits<P: AsRef<Path>>(img: &Image<f32>, header: &FitsHeader, file: P) -> Result<()> {
+	let file = file.as_ref();
+	let msg = format!("write {}", file.to_string_lossy());
+	let mut w = BufWriter::new(File::create(file).msg(&msg)?);
+
+	let mut head = String::new();
+	let mandatory = [
+		("SIMPLE", FitsValue::Bool(true), "FITS standard"),
+		("BITPIX", FitsValue::Int(-32), "32-bit float"),
+		("NAXIS", FitsValue::Int(2), ""),
+		("NAXIS1", FitsValue::Int(img.width() as i64), "width"),
+		("NAXIS2", FitsValue::Int(img.height() as i64), "height"),
+	];
+	for (key, value, comment) in &mandatory {
+		head += &card(key, value, comment);
+	}
+	for (key, value, comment) in &header.cards {
+		head += &card(key, value, comment);
+	}
+	head += &format!("{:<80}", "END");
+	w.write_all(pad(head.into_bytes(), b' ').as_slice()).msg(&msg)?;
+
+	let data: Vec<u8> = img.pixels().iter().flat_map(|v| v.to_be_bytes()).collect();
+	w.write_all(pad(data, 0).as_slice()).msg(&msg)?;
+	w.flush().msg(&msg)?;
+	Ok(())
+}
+
+// One 80-character header card: key, value (right-aligned to column 30 for numbers), comment.
+fn card(key: &str, value: &FitsValue, comment: &str) -> String {
+	let value = match value {
+		FitsValue::Bool(b) => format!("{:>20}", if *b { "T" } else { "F" }),
+		FitsValue::Int(i) => format!("{:>20}", i),
+		FitsValue::Float(f) => format!("{:>20}", float(*f)),
+		FitsValue::Str(s) => format!("'{:<8}'", s.replace('\'', "''")),
+	};
+	let mut card = format!("{:<8}= {}", key, value);
+	if !comment.is_empty() {
+		card += " / ";
+		card += comment;
+	}
+	card.truncate(CARD);
+	format!("{:<80}", card)
+}
+
+// Float in at most 20 characters, always recognizable as a float (with a '.' or exponent).
+fn float(f: f64) -> String {
+	let s = format!("{:?}", f);
+	if s.len() <= 20 && !s.contains('e') {
+		s
+	} else {
+		format!("{:.12E}", f)
+	}
+}
+
+// Pad to a whole number of blocks.
+fn pad(mut bytes: Vec<u8>, fill: u8) -> Vec<u8> {
+	let n = bytes.len().div_ceil(BLOCK) * BLOCK;
+	bytes.resize(n, fill);
+	bytes
+}
+
+impl From<bool> for FitsValue {
+	fn from(v: bool) -> Self {
+		FitsValue::Bool(v)
+	}
+}
+
+impl From<i64> for FitsValue {
+	fn from(v: i64) -> Self {
+		FitsValue::Int(v)
+	}
+}
+
+impl From<f64> for FitsValue {
+	fn from(v: f64) -> Self {
+		FitsValue::Float(v)
+	}
+}
+
+impl From<&str> for FitsValue {
+	fn from(v: &str) -> Self {
+		FitsValue::Str(v.into())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn fits() {
+		let img = Image::<f32>::from_fn(3, 2, |x, y| (x + 10 * y) as f32);
+		let viewport = Viewport::new(3.0, 2.0).with_center(vec2(1.0, -1.0));
+		let header = FitsHeader::new()
+			.with_time(12.5)
+			.with_viewport(&viewport, 3, 2)
+			.with("OBJECT", "it's", "")
+			.with("STEPS", 1234_i64, "");
+		assert_eq!(header.get("TIME"), Some(&FitsValue::Float(12.5)));
+
+		let file = std::env::temp_dir().join(format!("astrosim_fits_test_{}.fits", std::process::id()));
+		save_fits(&img, &header, &file).unwrap();
+		let bytes = std::fs::read(&file).unwrap();
+		std::fs::remove_file(&file).unwrap();
+
+		assert_eq!(bytes.len(), 2 * BLOCK);
+		let head = String::from_utf8(bytes[..BLOCK].to_vec()).unwrap();
+		let cards: Vec<&str> = (0..BLOCK / CARD).map(|i| &head[i * CARD..(i + 1) * CARD]).collect();
+		assert_eq!(cards[0], format!("{:<80}", "SIMPLE  =                    T / FITS standard"));
+		assert_eq!(cards[3].trim_end(), "NAXIS1  =                    3 / width");
+		assert_eq!(cards[5].trim_end(), "TIME    =                 12.5 / simulation time");
+		assert_eq!(cards[8].trim_end(), "CRPIX1  =                  2.5 / viewport center, pixels");
+		assert_eq!(cards[16].trim_end(), "OBJECT  = 'it''s   '");
+		assert_eq!(cards[17].trim_end(), "STEPS   =                 1234");
+		assert_eq!(cards[18].trim_end(), "END");
+		assert!(cards[19].trim().is_empty());
+
+		let data = &bytes[BLOCK..];
+		let value = |i: usize| f32::from_be_bytes([data[4 * i], data[4 * i + 1], data[4 * i + 2], data[4 * i + 3]]);
+		assert_eq!((0..6).map(value).collect::<Vec<_>>(), vec![0.0, 1.0, 2.0, 10.0, 11.0, 12.0]);
+		assert!(data[24..].iter().all(|&b| b == 0));
+
+		assert_eq!(float(1e-300), "1.000000000000E-300");
+	}
+}
@@ -3,6 +3,19 @@ use super::prelude::*;
 pub trait Forces {
 	fn set_accel(&self, particles: &[Particle], acc: &mut [vec2]);
 
+	/// Total potential energy of the particles,
+	/// consistent with the interactions included by set_accel.
+	/// Defaults to the gravitational energy of all pairs.
+	fn potential_energy(&self, particles: &[Particle]) -> f64 {
+		let mut energy = 0.0;
+		for (i, pi) in particles.iter().enumerate() {
+			for pj in &particles[(i + 1)..] {
+				energy -= pi.mass * pj.mass / (pj.pos - pi.pos).len();
+			}
+		}
+		energy
+	}
+
 	fn accel(&self, particles: &[Particle]) -> Vec<vec2> {
 		let mut acc = zeros(particles.len());
 		self.set_accel(particles, &mut acc);
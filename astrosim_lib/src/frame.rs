@@ -0,0 +1,115 @@
+use super::prelude::*;
+
+/// Reference frame for rendering.
+///
+/// In a co-rotating frame, bodies on resonant orbits with the reference body appear
+/// (nearly) stationary, showing e.g. Lagrange points and resonant libration directly.
+/// The frame is applied by moving and rotating the viewport along with the bodies.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum ReferenceFrame {
+	/// The simulation's own frame.
+	#[default]
+	Inertial,
+	/// Rotating with the first body about its centre of mass with the second (its primary),
+	/// with the first body on the +x axis.
+	CoRotating(usize, usize),
+	/// Centered on the first body, with the second body on the +x axis.
+	Line(usize, usize),
+}
+
+impl ReferenceFrame {
+	/// Co-rotating with the body named `name` in `meta`,
+	/// about its centre of mass with the most massive other body.
+	pub fn co_rotating(particles: &[Particle], meta: &[Meta], name: &str) -> Result<Self> {
+		let body = body(meta, name)?;
+		let primary = (0..particles.len()).filter(|&i| i != body).max_by(|&i, &j| particles[i].mass.total_cmp(&particles[j].mass));
+		match primary {
+			Some(primary) => Ok(ReferenceFrame::CoRotating(body, primary)),
+			None => err(format!("reference frame: {:?} has no other body to rotate about", name)),
+		}
+	}
+
+	/// Centered on body `origin`, rotating with the line to body `axis`.
+	pub fn line(meta: &[Meta], origin: &str, axis: &str) -> Result<Self> {
+		Ok(ReferenceFrame::Line(body(meta, origin)?, body(meta, axis)?))
+	}
+
+	/// Angle of the frame's x axis, counter-clockwise from the world's x axis.
+	pub fn angle(&self, particles: &[Particle]) -> f64 {
+		match self.origin_axis(particles) {
+			None => 0.0,
+			Some((_, axis)) => f64::atan2(axis.y, axis.x),
+		}
+	}
+
+	/// The world viewport that shows what `viewport` shows in this frame, for the current particle positions.
+	/// (The viewport's center and rotation are relative to the frame.)
+	pub fn viewport(&self, particles: &[Particle], viewport: &Viewport) -> Viewport {
+		let origin = match self.origin_axis(particles) {
+			None => return viewport.clone(),
+			Some((origin, _)) => origin,
+		};
+		let angle = self.angle(particles);
+		let (sin, cos) = angle.sin_cos();
+		let c = viewport.center;
+		Viewport {
+			center: origin + vec2(c.x * cos - c.y * sin, c.x * sin + c.y * cos),
+			rotation: viewport.rotation + angle,
+			..viewport.clone()
+		}
+	}
+
+	// Origin and x axis direction of the frame, if not inertial.
+	fn origin_axis(&self, particles: &[Particle]) -> Option<(vec2, vec2)> {
+		match *self {
+			ReferenceFrame::Inertial => None,
+			ReferenceFrame::CoRotating(b, p) => {
+				let (b, p) = (&particles[b], &particles[p]);
+				let m = b.mass + p.mass;
+				let com = if m > 0.0 { (b.mass * b.pos + p.mass * p.pos) / m } else { p.pos };
+				Some((com, b.pos - p.pos))
+			}
+			ReferenceFrame::Line(a, b) => Some((particles[a].pos, particles[b].pos - particles[a].pos)),
+		}
+	}
+}
+
+fn body(meta: &[Meta], name: &str) -> Result<usize> {
+	index_of(meta, name).ok_or_else(|| error(format!("reference frame: no body named {:?}", name)))
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn co_rotating() {
+		let particles = vec![
+			Particle::new(1.0, vec2(1.0, 1.0), vec2(0.0, 0.0)), //
+			Particle::new(1e-3, vec2(1.0, 3.0), vec2(0.0, 0.0)),
+		];
+		let meta = vec![Meta::new().with_name("sun"), Meta::new().with_name("jupiter")];
+		let base = Viewport::from_scale(4.0);
+		let (w, h) = (80, 80);
+
+		// about the centre of mass, 2 / 1001 from the sun.
+		let frame = ReferenceFrame::co_rotating(&particles, &meta, "jupiter").unwrap();
+		assert_eq!(frame, ReferenceFrame::CoRotating(1, 0));
+		let v = frame.viewport(&particles, &base);
+		let r = 2.0 * 1000.0 / 1001.0;
+		assert!((v.to_pixel(particles[1].pos, w, h) - vec2(40.5 + 10.0 * r, 40.5)).len() < 1e-9);
+		assert!((v.to_pixel(particles[0].pos, w, h) - vec2(40.5 - 10.0 * (2.0 - r), 40.5)).len() < 1e-9);
+
+		let frame = ReferenceFrame::line(&meta, "sun", "jupiter").unwrap();
+		let v = frame.viewport(&particles, &base);
+		assert!((v.to_pixel(particles[0].pos, w, h) - vec2(40.5, 40.5)).len() < 1e-9);
+		assert!((v.to_pixel(particles[1].pos, w, h) - vec2(60.5, 40.5)).len() < 1e-9);
+
+		// centered on jupiter, in the rotating frame.
+		let v = frame.viewport(&particles, &base.clone().with_center(vec2(2.0, 0.0)));
+		assert!((v.to_pixel(particles[1].pos, w, h) - vec2(40.5, 40.5)).len() < 1e-9);
+
+		assert_eq!(ReferenceFrame::Inertial.viewport(&particles, &base), base);
+		assert!(ReferenceFrame::co_rotating(&particles, &meta, "saturn").is_err());
+	}
+}
@@ -0,0 +1,306 @@
+use super::prelude::*;
+use std::cmp::Ordering;
+use std::fs::File;
+use std::io::{BufWriter, Write};
+use std::path::{Path, PathBuf};
+use std::str::FromStr;
+
+/// Orbital element to bin by, see `Elements`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Element {
+	/// Semi-major axis.
+	A,
+	/// Eccentricity.
+	E,
+	/// Argument of periapsis (radians, -PI..PI).
+	Omega,
+}
+
+/// Bins along one histogram axis: `bins` equal bins from `min` to `max`.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub struct Axis {
+	pub element: Element,
+	pub min: f64,
+	pub max: f64,
+	pub bins: u32,
+}
+
+/// Time-weighted histogram of particles in orbital-element space:
+/// 1D (e.g. semi-major axis, where the Kirkwood gaps show up as dips) or 2D (e.g. a-e).
+///
+///   let mut hist = Histogram::new(Axis::new(Element::A, 0.2, 1.0, 200));
+///   let asteroids = Selection::tag(sim.meta(), "asteroid");
+///   let mut exposure = Exposure::new();
+///   sim.advance_with_callback(100.0, |s| {
+///       if let Some(weight) = exposure.add(s.time(), s.next_dt()) {
+///           hist.accumulate(asteroids.select(s.particles()), &s.particles()[0], weight);
+///       }
+///       Ok(())
+///   })?;
+///   hist.write_csv("a.csv")?;
+///
+#[derive(Clone, Debug)]
+pub struct Histogram {
+	x: Axis,
+	y: Option<Axis>,
+	counts: Image<f32>,
+}
+
+impl Element {
+	/// This element of `particle`'s orbit around `primary` (NaN for the primary itself).
+	pub fn of(self, particle: &Particle, primary: &Particle) -> f64 {
+		let el = Elements::of(particle, primary);
+		match self {
+			Element::A => el.a(),
+			Element::E => el.e,
+			Element::Omega => el.omega,
+		}
+	}
+
+	fn name(self) -> &'static str {
+		match self {
+			Element::A => "a",
+			Element::E => "e",
+			Element::Omega => "omega",
+		}
+	}
+}
+
+impl Axis {
+	pub fn new(element: Element, min: f64, max: f64, bins: u32) -> Self {
+		Self { element, min, max, bins }
+	}
+
+	// Bin index of value v, if in range.
+	fn bin(&self, v: f64) -> Option<usize> {
+		let i = ((v - self.min) / (self.max - self.min) * (self.bins as f64)).floor();
+		if i >= 0.0 && i < self.bins as f64 {
+			Some(i as usize)
+		} else {
+			None
+		}
+	}
+
+	// Lower and upper edge of bin i.
+	fn edges(&self, i: usize) -> (f64, f64) {
+		let edge = |i: usize| self.min + (self.max - self.min) * (i as f64) / (self.bins as f64);
+		(edge(i), edge(i + 1))
+	}
+}
+
+impl Histogram {
+	/// 1D histogram.
+	pub fn new(x: Axis) -> Self {
+		Self {
+			x,
+			y: None,
+			counts: Image::new(x.bins, 1),
+		}
+	}
+
+	/// 2D histogram, x horizontally and y vertically when written as an image.
+	pub fn new_2d(x: Axis, y: Axis) -> Self {
+		Self {
+			x,
+			y: Some(y),
+			counts: Image::new(x.bins, y.bins),
+		}
+	}
+
+	/// Add `weight` (typically the time step) to the bins of the particles' orbits around `primary`.
+	/// Particles outside the range, and the primary itself, are not counted.
+	pub fn accumulate<'a, I>(&mut self, particles: I, primary: &Particle, weight: f32)
+	where
+		I: IntoIterator<Item = &'a Particle>,
+	{
+		for p in particles {
+			let x = self.x.bin(self.x.element.of(p, primary));
+			let y = match &self.y {
+				None => Some(0),
+				Some(y) => y.bin(y.element.of(p, primary)),
+			};
+			if let (Some(x), Some(y)) = (x, y) {
+				self.counts[y][x] += weight;
+			}
+		}
+	}
+
+	/// Accumulated weight per bin (1 row for 1D histograms).
+	pub fn counts(&self) -> &Image<f32> {
+		&self.counts
+	}
+
+	/// Write as CSV: bin edges and weight, one bin per line.
+	///
+	///   a_min,a_max,weight
+	///   0.2,0.204,1.25
+	///   ...
+	pub fn write_csv<P: AsRef<Path>>(&self, file: P) -> Result<()> {
+		let file = file.as_ref();
+		let msg = format!("write {}", file.to_string_lossy());
+		let mut w = BufWriter::new(File::create(file).msg(&msg)?);
+		let (x, y) = (self.x, self.y);
+		match y {
+			None => writeln!(w, "{0}_min,{0}_max,weight", x.element.name())?,
+			Some(y) => writeln!(w, "{0}_min,{0}_max,{1}_min,{1}_max,weight", x.element.name(), y.element.name())?,
+		}
+		for j in 0..self.counts.height() {
+			for i in 0..self.counts.width() {
+				let (x0, x1) = x.edges(i);
+				match y {
+					None => writeln!(w, "{},{},{}", x0, x1, self.counts[j][i])?,
+					Some(y) => {
+						let (y0, y1) = y.edges(j);
+						writeln!(w, "{},{},{},{},{}", x0, x1, y0, y1, self.counts[j][i])?
+					}
+				}
+			}
+		}
+		w.flush().msg(&msg)?;
+		Ok(())
+	}
+
+	/// Tone-mapped image: one pixel per bin (a single row for 1D histograms).
+	pub fn image(&self, tone_map: &ToneMap) -> image::RgbaImage {
+		tone_map.apply(&self.counts)
+	}
+
+	/// Reset all bins to zero.
+	pub fn clear(&mut self) {
+		self.counts.clear();
+	}
+}
+
+/// Output sink accumulating a histogram after every step,
+/// over the whole run, and (over)writing it when output is scheduled:
+/// histogram.csv, and for 2D histograms histogram.png.
+pub struct HistogramFile {
+	dir: PathBuf,
+	hist: Histogram,
+	primary: usize,
+	selection: Selection,
+	tone_map: ToneMap,
+	exposure: Exposure,
+}
+
+impl HistogramFile {
+	pub const CSV_FILE: &'static str = "histogram.csv";
+	pub const IMAGE_FILE: &'static str = "histogram.png";
+
+	/// Histogram of the orbits of the `selection` of particles around the body with index `primary`.
+	pub fn new(dir: &Path, hist: Histogram, primary: usize, selection: Selection) -> Self {
+		Self {
+			dir: dir.into(),
+			hist,
+			primary,
+			selection,
+			tone_map: ToneMap::default(),
+			exposure: Exposure::new(),
+		}
+	}
+
+	pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+		self.tone_map = tone_map;
+		self
+	}
+
+	/// Weight each step by the elapsed time like this (default: rectangle rule).
+	pub fn with_exposure(mut self, exposure: Exposure) -> Self {
+		self.exposure = exposure;
+		self
+	}
+}
+
+impl<F: Forces> OutputSink<F> for HistogramFile {
+	fn step(&mut self, sim: &Stepper<F>) -> Result<()> {
+		if let Some(weight) = self.exposure.add(sim.time(), sim.next_dt()) {
+			let ps = sim.particles();
+			self.hist.accumulate(self.selection.select(ps), &ps[self.primary], weight);
+		}
+		Ok(())
+	}
+
+	fn output(&mut self, _sim: &Stepper<F>) -> Result<()> {
+		self.hist.write_csv(self.dir.join(Self::CSV_FILE))?;
+		if self.hist.y.is_some() {
+			self.hist.image(&self.tone_map).save(self.dir.join(Self::IMAGE_FILE))?;
+		}
+		Ok(())
+	}
+
+	fn exact_times(&self) -> bool {
+		false
+	}
+}
+
+impl FromStr for Element {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"a" => Ok(Element::A),
+			"e" => Ok(Element::E),
+			"omega" => Ok(Element::Omega),
+			_ => err(format!("unknown orbital element {:?}, want a, e or omega", s)),
+		}
+	}
+}
+
+impl FromStr for Axis {
+	type Err = Error;
+
+	/// "element:min:max:bins", e.g. "a:0.2:1.0:200".
+	fn from_str(s: &str) -> Result<Self> {
+		let msg = || format!("histogram axis {:?}: want element:min:max:bins, e.g. a:0.2:1.0:200", s);
+		let f: Vec<&str> = s.split(':').collect();
+		if f.len() != 4 {
+			return err(msg());
+		}
+		let axis = Axis {
+			element: f[0].parse()?,
+			min: f[1].parse().map_err(|_| error(msg()))?,
+			max: f[2].parse().map_err(|_| error(msg()))?,
+			bins: f[3].parse().map_err(|_| error(msg()))?,
+		};
+		if axis.max.partial_cmp(&axis.min) != Some(Ordering::Greater) || axis.bins == 0 {
+			return err(msg());
+		}
+		Ok(axis)
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn histogram() {
+		let sun = Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0));
+		// circular orbits: a = r, e = 0.
+		let circular = |r: f64| Particle::new(0.0, vec2(r, 0.0), vec2(0.0, f64::sqrt(1.0 / r)));
+		let particles = vec![sun.clone(), circular(0.45), circular(0.55), circular(0.56), circular(2.0)];
+
+		let mut h = Histogram::new("a:0:1:10".parse().unwrap());
+		h.accumulate(&particles, &sun, 0.5);
+		h.accumulate(&particles, &sun, 0.25);
+		assert_eq!(h.counts()[0][4], 0.75);
+		assert_eq!(h.counts()[0][5], 1.5);
+		assert_eq!(h.counts().pixels().iter().sum::<f32>(), 2.25); // sun and a = 2 not counted
+
+		let mut h2 = Histogram::new_2d(Axis::new(Element::A, 0.0, 1.0, 10), Axis::new(Element::E, 0.0, 0.5, 5));
+		h2.accumulate(&particles, &sun, 1.0);
+		assert_eq!(h2.counts()[0][5], 2.0);
+
+		let file = std::env::temp_dir().join(format!("astrosim_histogram_test_{}.csv", std::process::id()));
+		h2.write_csv(&file).unwrap();
+		let csv = std::fs::read_to_string(&file).unwrap();
+		let lines: Vec<&str> = csv.lines().collect();
+		assert_eq!(lines.len(), 1 + 50);
+		assert_eq!(lines[0], "a_min,a_max,e_min,e_max,weight");
+		assert_eq!(lines[6], "0.5,0.6,0,0.1,2");
+		std::fs::remove_file(&file).unwrap();
+
+		assert!("a:1:0:10".parse::<Axis>().is_err());
+		assert!("i:0:1:10".parse::<Axis>().is_err());
+	}
+}
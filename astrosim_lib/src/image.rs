@@ -1,3 +1,4 @@
+use image::{ImageBuffer, Luma, Primitive};
 use std::ops::{Index, IndexMut};
 
 /// 2D rectangular array of generic values.
@@ -7,7 +8,7 @@ pub struct Image<C> {
 	values: Vec<C>,
 }
 
-impl<'a, C> Image<C>
+impl<C> Image<C>
 where
 	C: Copy + Default,
 {
@@ -19,15 +20,16 @@ where
 		}
 	}
 
-	//pub fn from_fn<F: Fn(i32, i32) -> C>((w, h): (i32, i32), f: F) -> Self {
-	//	let mut img = Self::new(w, h);
-	//	for iy in 0..(h as usize) {
-	//		for ix in 0..(w as usize) {
-	//			img[iy][ix] = f(ix as i32, iy as i32);
-	//		}
-	//	}
-	//	img
-	//}
+	/// from_fn constructs an Image with given width and height, and pixel (x, y) set to f(x, y).
+	pub fn from_fn<F: FnMut(usize, usize) -> C>(w: u32, h: u32, mut f: F) -> Self {
+		let mut img = Self::new(w, h);
+		for iy in 0..(h as usize) {
+			for ix in 0..(w as usize) {
+				img[iy][ix] = f(ix, iy);
+			}
+		}
+		img
+	}
 
 	pub fn at(&self, p: (i32, i32)) -> C {
 		self[p.1 as usize][p.0 as usize]
@@ -57,6 +59,41 @@ where
 	pub fn pixels_mut(&mut self) -> &mut [C] {
 		&mut self.values
 	}
+
+	/// set all pixels to c.
+	pub fn fill(&mut self, c: C) {
+		for v in &mut self.values {
+			*v = c;
+		}
+	}
+
+	/// set all pixels to the default (zero).
+	pub fn clear(&mut self) {
+		self.fill(C::default())
+	}
+
+	/// new image with f applied to each pixel.
+	pub fn map<D: Copy + Default, F: FnMut(C) -> D>(&self, f: F) -> Image<D> {
+		Image {
+			dim: self.dim,
+			values: self.values.iter().copied().map(f).collect(),
+		}
+	}
+
+	/// the w x h rectangle with top-left corner (x, y), clipped to the image.
+	pub fn crop(&self, x: u32, y: u32, w: u32, h: u32) -> Self {
+		let (x, y) = (usize::min(x as usize, self.width()), usize::min(y as usize, self.height()));
+		let w = usize::min(w as usize, self.width() - x);
+		let h = usize::min(h as usize, self.height() - y);
+		Self::from_fn(w as u32, h as u32, |ix, iy| self[y + iy][x + ix])
+	}
+
+	/// larger image where each pixel is repeated factor x factor times (nearest-neighbour), factor > 0.
+	pub fn upsample(&self, factor: u32) -> Self {
+		assert!(factor > 0, "Image::upsample: factor must be > 0");
+		let f = factor as usize;
+		Self::from_fn(self.width() as u32 * factor, self.height() as u32 * factor, |x, y| self[y / f][x / f])
+	}
 }
 
 impl<C> Default for Image<C>
@@ -93,13 +130,79 @@ where
 }
 
 impl Image<f32> {
+	/// multiply all pixels by s (e.g. s < 1 to let old density fade out).
+	pub fn scale(&mut self, s: f32) {
+		for v in &mut self.values {
+			*v *= s;
+		}
+	}
+
+	/// add other, of the same size, pixel by pixel.
+	pub fn add(&mut self, other: &Self) {
+		assert_eq!(self.dim, other.dim, "Image::add: size mismatch");
+		for (v, &o) in self.values.iter_mut().zip(&other.values) {
+			*v += o;
+		}
+	}
+
+	/// linear interpolation towards other, of the same size: self * (1 - t) + other * t.
+	pub fn blend(&mut self, other: &Self, t: f32) {
+		assert_eq!(self.dim, other.dim, "Image::blend: size mismatch");
+		for (v, &o) in self.values.iter_mut().zip(&other.values) {
+			*v = *v * (1.0 - t) + o * t;
+		}
+	}
+
+	/// blurred with a Gaussian of standard deviation sigma pixels (out to 3 sigma),
+	/// counting outside the image as zero.
+	pub fn blur(&self, sigma: f64) -> Self {
+		if sigma.is_nan() || sigma <= 0.0 {
+			return self.clone();
+		}
+		let r = (3.0 * sigma).ceil() as i64;
+		let kernel: Vec<f32> = (-r..=r).map(|i| f64::exp(-0.5 * (i as f64 / sigma).powi(2)) as f32).collect();
+		let norm: f32 = kernel.iter().sum();
+		let kernel: Vec<f32> = kernel.iter().map(|k| k / norm).collect();
+
+		// separable: horizontal, then vertical.
+		let (w, h) = (self.width() as i64, self.height() as i64);
+		let convolve = |src: &Self, dx: i64, dy: i64| {
+			Self::from_fn(w as u32, h as u32, |x, y| {
+				let mut sum = 0.0;
+				for (i, k) in (-r..=r).zip(&kernel) {
+					let (sx, sy) = (x as i64 + i * dx, y as i64 + i * dy);
+					if sx >= 0 && sx < w && sy >= 0 && sy < h {
+						sum += k * src[sy as usize][sx as usize];
+					}
+				}
+				sum
+			})
+		};
+		convolve(&convolve(self, 1, 0), 0, 1)
+	}
+
+	/// smaller image where each pixel is the average of factor x factor pixels, factor > 0,
+	/// dropping pixels beyond a whole multiple of factor (right and bottom edges).
+	pub fn downsample(&self, factor: u32) -> Self {
+		assert!(factor > 0, "Image::downsample: factor must be > 0");
+		let f = factor as usize;
+		let n = (f * f) as f32;
+		Self::from_fn(self.width() as u32 / factor, self.height() as u32 / factor, |x, y| {
+			let mut sum = 0.0;
+			for iy in y * f..(y + 1) * f {
+				sum += self[iy][x * f..(x + 1) * f].iter().sum::<f32>();
+			}
+			sum / n
+		})
+	}
+
 	pub fn raw_bgra(&self, scale: f32) -> Vec<u8> {
 		let (w, h) = self.dimensions();
 		let mut raw = Vec::with_capacity((w * h * 4) as usize);
 		for iy in 0..h {
 			for ix in 0..w {
 				let value = self[iy as usize][ix as usize];
-				let color = i32::max(255, (value * scale) as i32) as u8;
+				let color = i32::clamp((value * scale) as i32, 0, 255) as u8;
 				raw.push(color);
 				raw.push(color);
 				raw.push(color);
@@ -109,3 +212,66 @@ impl Image<f32> {
 		raw
 	}
 }
+
+/// grayscale buffers of the image crate, e.g. `image::GrayImage` for Image<u8>.
+impl<T: Primitive + Default + 'static> From<&ImageBuffer<Luma<T>, Vec<T>>> for Image<T> {
+	fn from(buf: &ImageBuffer<Luma<T>, Vec<T>>) -> Self {
+		Image::from_fn(buf.width(), buf.height(), |x, y| buf.get_pixel(x as u32, y as u32)[0])
+	}
+}
+
+impl<T: Primitive + Default + 'static> From<&Image<T>> for ImageBuffer<Luma<T>, Vec<T>> {
+	fn from(img: &Image<T>) -> Self {
+		ImageBuffer::from_raw(img.width() as u32, img.height() as u32, img.pixels().to_vec()).unwrap()
+	}
+}
+
+impl From<&image::RgbaImage> for Image<[u8; 4]> {
+	fn from(buf: &image::RgbaImage) -> Self {
+		Image::from_fn(buf.width(), buf.height(), |x, y| buf.get_pixel(x as u32, y as u32).0)
+	}
+}
+
+impl From<&Image<[u8; 4]>> for image::RgbaImage {
+	fn from(img: &Image<[u8; 4]>) -> Self {
+		image::RgbaImage::from_fn(img.width() as u32, img.height() as u32, |x, y| image::Rgba(img[y as usize][x as usize]))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn arithmetic() {
+		let mut a = Image::<f32>::from_fn(4, 2, |x, y| (x + 10 * y) as f32);
+		assert_eq!(a[1][3], 13.0);
+		assert_eq!(a.crop(2, 1, 5, 5).pixels(), &[12.0, 13.0]);
+		assert_eq!(a.downsample(2).pixels(), &[5.5, 7.5]);
+		assert_eq!(a.downsample(2).upsample(2).crop(0, 0, 4, 1).pixels(), &[5.5, 5.5, 7.5, 7.5]);
+
+		let b = a.map(|v| v * 2.0);
+		a.scale(0.5);
+		a.add(&b);
+		assert_eq!(a[1][3], 32.5);
+		a.blend(&b, 1.0);
+		assert_eq!(a, b);
+		a.clear();
+		assert!(a.pixels().iter().all(|&v| v == 0.0));
+
+		let gray: image::GrayImage = (&Image::<u8>::from_fn(3, 2, |x, y| (x * y) as u8)).into();
+		assert_eq!(gray.get_pixel(2, 1)[0], 2);
+		assert_eq!(Image::<u8>::from(&gray)[1][2], 2);
+	}
+
+	#[test]
+	fn blur() {
+		let mut img = Image::<f32>::new(21, 21);
+		img[10][10] = 1.0;
+		let blurred = img.blur(2.0);
+		assert!((blurred.pixels().iter().sum::<f32>() - 1.0).abs() < 1e-5);
+		assert!(blurred[10][10] < 0.1 && blurred[10][10] > blurred[10][12]);
+		assert_eq!(blurred[10][12], blurred[12][10]);
+		assert_eq!(blurred[10][12], blurred[10][8]);
+	}
+}
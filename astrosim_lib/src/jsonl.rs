@@ -0,0 +1,171 @@
+use super::prelude::*;
+use std::fmt::Write as _;
+use std::io::Write;
+
+/// Output sink writing one JSON object per line ("JSON Lines"),
+/// e.g. to stdout, for piping a run into another program. Each line holds
+/// time, step, dt and error estimate, and optionally diagnostics and particle states:
+///
+///   {"time":0.5,"step":42,"dt":0.01,"error":0.02,"diagnostics":{...},"particles":[{"name":"sun","m":1.0,"x":0.0,...},...]}
+///
+/// Lines are flushed immediately, so that readers see them as they are written.
+pub struct JsonLines<W: Write> {
+	out: W,
+	particles: bool,
+	diagnostics: bool,
+	initial: Option<Diagnostics>,
+}
+
+impl<W: Write> JsonLines<W> {
+	pub fn new(out: W) -> Self {
+		Self {
+			out,
+			particles: false,
+			diagnostics: false,
+			initial: None,
+		}
+	}
+
+	/// Include the state of all particles.
+	pub fn with_particles(mut self, enabled: bool) -> Self {
+		self.particles = enabled;
+		self
+	}
+
+	/// Include energy, momentum and their drift (see `Diagnostics`).
+	pub fn with_diagnostics(mut self, enabled: bool) -> Self {
+		self.diagnostics = enabled;
+		self
+	}
+
+	fn line<F: Forces>(&mut self, sim: &Stepper<F>) -> String {
+		let mut s = String::new();
+		s += &format!(
+			"{{\"time\":{},\"step\":{},\"dt\":{},\"error\":{}",
+			num(sim.time()),
+			sim.step_count(),
+			num(sim.dt()),
+			num(sim.relative_error())
+		);
+
+		if self.diagnostics {
+			let d = sim.diagnostics();
+			let drift = d.drift(self.initial.get_or_insert_with(|| d.clone()));
+			let _ = write!(
+				s,
+				",\"diagnostics\":{{\"energy\":{},\"kinetic_energy\":{},\"potential_energy\":{},\"momentum\":[{},{}],\"angular_momentum\":{},\"energy_drift\":{},\"momentum_drift\":{},\"angular_momentum_drift\":{}}}",
+				num(d.energy()),
+				num(d.kinetic_energy),
+				num(d.potential_energy),
+				num(d.momentum.x),
+				num(d.momentum.y),
+				num(d.angular_momentum),
+				num(drift.energy),
+				num(drift.momentum),
+				num(drift.angular_momentum)
+			);
+		}
+
+		if self.particles {
+			s += ",\"particles\":[";
+			for (i, (p, m)) in sim.particles().iter().zip(sim.meta()).enumerate() {
+				if i != 0 {
+					s += ",";
+				}
+				s += "{";
+				if let Some(name) = &m.name {
+					let _ = write!(s, "\"name\":{},", string(name));
+				}
+				if let Some(tag) = &m.tag {
+					let _ = write!(s, "\"tag\":{},", string(tag));
+				}
+				let _ = write!(
+					s,
+					"\"m\":{},\"x\":{},\"y\":{},\"vx\":{},\"vy\":{}}}",
+					num(p.mass),
+					num(p.pos.x),
+					num(p.pos.y),
+					num(p.vel.x),
+					num(p.vel.y)
+				);
+			}
+			s += "]";
+		}
+		s += "}";
+		s
+	}
+}
+
+impl<F: Forces, W: Write> OutputSink<F> for JsonLines<W> {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		let line = self.line(sim);
+		writeln!(self.out, "{}", line)?;
+		self.out.flush()?;
+		Ok(())
+	}
+}
+
+// JSON number, in exponent notation if very large or small.
+// JSON has no NaN or infinity, these become null.
+fn num(v: f64) -> String {
+	if v.is_finite() {
+		format!("{:?}", v)
+	} else {
+		"null".into()
+	}
+}
+
+// JSON string literal.
+fn string(v: &str) -> String {
+	let mut s = String::with_capacity(v.len() + 2);
+	s.push('"');
+	for c in v.chars() {
+		match c {
+			'"' => s += "\\\"",
+			'\\' => s += "\\\\",
+			'\n' => s += "\\n",
+			'\r' => s += "\\r",
+			'\t' => s += "\\t",
+			c if (c as u32) < 0x20 => {
+				let _ = write!(s, "\\u{:04x}", c as u32);
+			}
+			c => s.push(c),
+		}
+	}
+	s.push('"');
+	s
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn json_lines() {
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), //
+			Particle::new(0.0, vec2(1.0, 0.5), vec2(0.0, 1.0)),
+		];
+		let meta = vec![Meta::new().with_name("s\"un"), Meta::new().with_tag("asteroid")];
+		let sim = Stepper::new(particles, BruteForce::new()).with_meta(meta);
+		let mut sink = JsonLines::new(Vec::new()).with_particles(true).with_diagnostics(true);
+		sink.output(&sim).unwrap();
+		sink.output(&sim).unwrap();
+
+		let out = String::from_utf8(sink.out).unwrap();
+		let lines: Vec<&str> = out.lines().collect();
+		assert_eq!(lines.len(), 2);
+		assert!(lines[0].starts_with("{\"time\":0.0,\"step\":0,\"dt\":"));
+		assert!(lines[0].contains("\"energy_drift\":0.0,"));
+		assert!(lines[0].ends_with(
+			",\"particles\":[{\"name\":\"s\\\"un\",\"m\":1.0,\"x\":0.0,\"y\":0.0,\"vx\":0.0,\"vy\":0.0},{\"tag\":\"asteroid\",\"m\":0.0,\"x\":1.0,\"y\":0.5,\"vx\":0.0,\"vy\":1.0}]}"
+		));
+	}
+
+	#[test]
+	fn non_finite() {
+		assert_eq!(num(f64::NAN), "null");
+		assert_eq!(num(-INF), "null");
+		assert_eq!(num(1e-300), "1e-300");
+	}
+}
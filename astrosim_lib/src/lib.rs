@@ -1,11 +1,41 @@
+// Force loops index particles and accelerations alike.
+#![allow(clippy::needless_range_loop)]
+// Folds spell out their closures, e.g. Stepper::relative_error.
+#![allow(clippy::redundant_closure)]
+// u64::is_multiple_of needs a newer Rust than the crates otherwise do.
+#![allow(clippy::manual_is_multiple_of)]
+
+mod animation;
+mod binio;
 mod brute_force;
+mod checkpoint;
+mod composite;
+mod diagnostics;
+mod draw;
+mod elements;
+mod exposure;
+mod fits;
 mod forces;
+mod frame;
+mod histogram;
 mod image;
+mod jsonl;
 mod math;
+mod npy;
 mod outputs;
+mod overlay;
 mod partial_force;
 mod particle;
+mod projection;
 mod render;
+mod schedule;
+mod selection;
+mod snapshot;
+mod splat;
 mod stepper;
+mod svg;
+mod tonemap;
+mod trail;
+mod viewport;
 
 pub mod prelude;
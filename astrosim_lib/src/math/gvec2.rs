@@ -159,4 +159,10 @@ where
 	pub fn len2(self) -> T {
 		self.dot(self)
 	}
+
+	/// Z-component of the cross product (as if both vectors lie in the XY plane).
+	#[inline]
+	pub fn cross(self, rhs: gvec2<T>) -> T {
+		self.x * rhs.y - self.y * rhs.x
+	}
 }
@@ -1,4 +1,7 @@
+// Constructors dvec2(), fvec2() are kept for symmetry with vec2().
+#[allow(dead_code)]
 mod dvec2;
+#[allow(dead_code)]
 mod fvec2;
 mod gvec2;
 mod util;
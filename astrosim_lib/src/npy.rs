@@ -0,0 +1,435 @@
+use super::binio::*;
+use super::prelude::*;
+use std::fs;
+use std::fs::{File, OpenOptions};
+use std::io;
+use std::io::{BufReader, BufWriter, Read, Seek, SeekFrom, Write};
+use std::path::{Path, PathBuf};
+
+const MAGIC: &[u8] = b"\x93NUMPY";
+
+/// Total header length (magic, version and dictionary), fixed so that the header
+/// can be rewritten in place as the array grows. A multiple of 64, as recommended by the format.
+const HEADER_LEN: usize = 128;
+
+/// Writes a NumPy .npy file of f64 (little-endian, C order), one row at a time.
+///
+/// The first dimension grows with each row appended. The header is updated on `flush` (and drop),
+/// so the file is a valid array after each flush (e.g. while the simulation is still running).
+pub struct NpyWriter {
+	out: BufWriter<File>,
+	row_shape: Vec<usize>,
+	rows: usize,
+}
+
+impl NpyWriter {
+	/// Create a file holding zero rows of shape `row_shape`.
+	/// E.g. `row_shape = [n, 2]` for an array of shape `(rows, n, 2)`.
+	pub fn create(file: &Path, row_shape: &[usize]) -> Result<Self> {
+		let msg = format!("create {}", file.to_string_lossy());
+		let mut w = Self {
+			out: BufWriter::new(File::create(file).msg(&msg)?),
+			row_shape: row_shape.into(),
+			rows: 0,
+		};
+		w.write_header()?;
+		Ok(w)
+	}
+
+	/// Open an existing file (written by `NpyWriter`) to append more rows,
+	/// which must have shape `row_shape`.
+	/// A partially written row at the end (e.g. after a crash) is discarded.
+	pub fn append(file: &Path, row_shape: &[usize]) -> Result<Self> {
+		let msg = format!("append {}", file.to_string_lossy());
+		let mut f = OpenOptions::new().read(true).write(true).open(file).msg(&msg)?;
+		let (shape, header_len) = read_header(&mut f).map_err(|e| error(format!("{}: {}", &msg, e)))?;
+		if header_len != HEADER_LEN || shape.len() != row_shape.len() + 1 || shape[1..] != *row_shape {
+			return err(format!("{}: have shape {:?}, want (rows, {:?})", &msg, shape, row_shape));
+		}
+		if checked_len(&shape, f.metadata().msg(&msg)?.len().saturating_sub(HEADER_LEN as u64)).is_none() {
+			return err(format!("{}: shape {:?} does not fit the file size", &msg, shape));
+		}
+		let rows = shape[0];
+		f.set_len((HEADER_LEN + rows * row_len(row_shape) * 8) as u64).msg(&msg)?;
+		f.seek(SeekFrom::End(0))?;
+		Ok(Self {
+			out: BufWriter::new(f),
+			row_shape: row_shape.into(),
+			rows,
+		})
+	}
+
+	/// Number of rows written so far.
+	pub fn rows(&self) -> usize {
+		self.rows
+	}
+
+	/// Discard all but the first `rows` rows.
+	pub fn truncate(&mut self, rows: usize) -> Result<()> {
+		if rows < self.rows {
+			self.out.flush()?;
+			self.out.get_ref().set_len((HEADER_LEN + rows * row_len(&self.row_shape) * 8) as u64)?;
+			self.rows = rows;
+			self.write_header()?;
+		}
+		Ok(())
+	}
+
+	/// Append one row, with as many elements as the row shape holds.
+	pub fn push_row(&mut self, row: &[f64]) -> Result<()> {
+		if row.len() != row_len(&self.row_shape) {
+			return err(format!("npy: row of {} elements does not fit shape {:?}", row.len(), self.row_shape));
+		}
+		for v in row {
+			self.out.write_all(&v.to_le_bytes())?;
+		}
+		self.rows += 1;
+		Ok(())
+	}
+
+	/// Update the header to the rows written so far, and flush them to the file.
+	pub fn flush(&mut self) -> Result<()> {
+		self.write_header()
+	}
+
+	fn write_header(&mut self) -> Result<()> {
+		let mut shape = vec![self.rows];
+		shape.extend(&self.row_shape);
+		self.out.seek(SeekFrom::Start(0))?;
+		self.out.write_all(&header(&shape)?)?;
+		self.out.seek(SeekFrom::End(0))?;
+		self.out.flush()?;
+		Ok(())
+	}
+}
+
+impl Drop for NpyWriter {
+	fn drop(&mut self) {
+		let _ = self.write_header();
+	}
+}
+
+// Number of elements in a row.
+fn row_len(row_shape: &[usize]) -> usize {
+	row_shape.iter().product()
+}
+
+// Version 1.0 header, padded to HEADER_LEN.
+fn header(shape: &[usize]) -> Result<Vec<u8>> {
+	let dims: Vec<String> = shape.iter().map(|d| d.to_string()).collect();
+	// Python tuple syntax: (k,) in 1D.
+	let shape = match dims.len() {
+		1 => format!("({},)", dims[0]),
+		_ => format!("({})", dims.join(", ")),
+	};
+	let dict = format!("{{'descr': '<f8', 'fortran_order': False, 'shape': {}, }}", shape);
+
+	let mut h = Vec::with_capacity(HEADER_LEN);
+	h.extend(MAGIC);
+	h.extend(&[1, 0]);
+	h.extend(&((HEADER_LEN - MAGIC.len() - 4) as u16).to_le_bytes());
+	h.extend(dict.bytes());
+	if h.len() >= HEADER_LEN {
+		return err(format!("npy: header too long for shape {}", shape));
+	}
+	h.resize(HEADER_LEN - 1, b' ');
+	h.push(b'\n');
+	Ok(h)
+}
+
+// Read a .npy header, return the array shape and total header length.
+// Only little-endian f64 arrays in C order are supported.
+fn read_header(r: &mut dyn Read) -> Result<(Vec<usize>, usize)> {
+	r.read_magic(MAGIC)?;
+	let version = r.read_u8()?;
+	let _minor = r.read_u8()?;
+	let (dict_len, prefix) = match version {
+		1 => (r.read_u16()? as usize, MAGIC.len() + 4),
+		2 | 3 => (r.read_u32()? as usize, MAGIC.len() + 6),
+		v => return err(format!("npy: unsupported version {}", v)),
+	};
+	// Not allocated up front: the length may be corrupt.
+	let mut dict = Vec::new();
+	r.take(dict_len as u64).read_to_end(&mut dict)?;
+	if dict.len() != dict_len {
+		return err("npy: truncated header");
+	}
+	let dict = String::from_utf8(dict)?;
+
+	if !dict.contains("'descr': '<f8'") || !dict.contains("'fortran_order': False") {
+		return err(format!("npy: only little-endian f8 in C order is supported, got {}", dict.trim()));
+	}
+	let shape = dict
+		.split("'shape': (")
+		.nth(1)
+		.and_then(|s| s.split(')').next())
+		.ok_or_else(|| error(format!("npy: no shape in header {}", dict.trim())))?;
+	let shape = shape
+		.split(',')
+		.map(str::trim)
+		.filter(|s| !s.is_empty())
+		.map(|s| s.parse::<usize>().map_err(|e| error(format!("npy: bad shape {}: {}", shape, e))))
+		.collect::<Result<Vec<usize>>>()?;
+	Ok((shape, prefix + dict_len))
+}
+
+// Number of elements of an array of this shape, if they fit in `bytes` (not if they overflow).
+fn checked_len(shape: &[usize], bytes: u64) -> Option<usize> {
+	let n = shape.iter().try_fold(1usize, |n, &len| n.checked_mul(len))?;
+	if (n as u64).checked_mul(8)? <= bytes {
+		Some(n)
+	} else {
+		None
+	}
+}
+
+/// Read a .npy file of f64 (as written by `NpyWriter` or NumPy). Returns the shape and the elements, in C order.
+pub fn read_npy(file: &Path) -> Result<(Vec<usize>, Vec<f64>)> {
+	let msg = format!("read {}", file.to_string_lossy());
+	let mut r = BufReader::new(File::open(file).msg(&msg)?);
+	let (shape, header_len) = read_header(&mut r).map_err(|e| error(format!("{}: {}", &msg, e)))?;
+	let file_len = r.get_ref().metadata().msg(&msg)?.len();
+	let n = match checked_len(&shape, file_len.saturating_sub(header_len as u64)) {
+		Some(n) => n,
+		None => return err(format!("{}: shape {:?} does not fit the file size {}", &msg, shape, file_len)),
+	};
+	let mut data = Vec::with_capacity(n);
+	for _i in 0..n {
+		data.push(r.read_f64().map_err(|e| error(format!("{}: {}", &msg, e)))?);
+	}
+	Ok((shape, data))
+}
+
+/// Bundle .npy files into an (uncompressed) .npz archive,
+/// as `numpy.savez` would. Arrays are given as (name, npy file).
+pub fn write_npz(file: &Path, arrays: &[(&str, PathBuf)]) -> Result<()> {
+	let msg = format!("create {}", file.to_string_lossy());
+	let mut zip = zip::ZipWriter::new(BufWriter::new(File::create(file).msg(&msg)?));
+	let options = zip::write::FileOptions::default().compression_method(zip::CompressionMethod::Stored).large_file(true);
+	for (name, npy) in arrays {
+		zip.start_file(format!("{}.npy", name), options)?;
+		io::copy(&mut File::open(npy).msg(&format!("read {}", npy.to_string_lossy()))?, &mut zip)?;
+	}
+	zip.finish()?.flush()?;
+	Ok(())
+}
+
+/// Extract all arrays from a .npz archive into .npy files in `dir`.
+pub fn extract_npz(file: &Path, dir: &Path) -> Result<()> {
+	let msg = format!("read {}", file.to_string_lossy());
+	let mut zip = zip::ZipArchive::new(File::open(file).msg(&msg)?)?;
+	for i in 0..zip.len() {
+		let mut entry = zip.by_index(i)?;
+		// only plain file names, no paths.
+		let name = Path::new(entry.name())
+			.file_name()
+			.ok_or_else(|| error(format!("{}: bad entry {}", &msg, entry.name())))?
+			.to_owned();
+		io::copy(&mut entry, &mut File::create(dir.join(name))?)?;
+	}
+	Ok(())
+}
+
+/// Output sink writing snapshots of the particles as NumPy arrays:
+///
+///   time.npy: shape (snapshots,)
+///   pos.npy:  shape (snapshots, particles, 2)
+///   vel.npy:  shape (snapshots, particles, 2)
+///   mass.npy: shape (particles,)
+///
+/// Optionally bundled into trajectory.npz at the end of the run
+/// (the .npy files are written during the run, so that they can be inspected while running).
+pub struct NpyTrajectory {
+	dir: PathBuf,
+	resume: Option<f64>,
+	npz: bool,
+	writers: Option<[NpyWriter; 3]>, // time, pos, vel: opened on first output, when the number of particles is known.
+}
+
+impl NpyTrajectory {
+	pub const NPZ_FILE: &'static str = "trajectory.npz";
+	const ARRAYS: [&'static str; 4] = ["time", "pos", "vel", "mass"];
+
+	/// Write to `dir`, or append to existing arrays when resuming from `resume` time
+	/// (discarding rows after it). If `npz`, bundle the arrays into a single .npz file when closed.
+	pub fn new(dir: &Path, resume: Option<f64>, npz: bool) -> Result<Self> {
+		let s = Self {
+			dir: dir.into(),
+			resume,
+			npz,
+			writers: None,
+		};
+		// Resuming a run that bundled its arrays: unpack them to append.
+		let bundle = dir.join(Self::NPZ_FILE);
+		if resume.is_some() && npz && bundle.exists() && !s.file("time").exists() {
+			extract_npz(&bundle, dir)?;
+		}
+		Ok(s)
+	}
+
+	fn file(&self, array: &str) -> PathBuf {
+		self.dir.join(format!("{}.npy", array))
+	}
+
+	fn open(&self, n: usize, masses: &[f64]) -> Result<[NpyWriter; 3]> {
+		let open = |array: &str, row_shape: &[usize]| {
+			let file = self.file(array);
+			if self.resume.is_some() && file.exists() {
+				NpyWriter::append(&file, row_shape)
+			} else {
+				NpyWriter::create(&file, row_shape)
+			}
+		};
+		let mut writers = [open("time", &[])?, open("pos", &[n, 2])?, open("vel", &[n, 2])?];
+		if let Some(resume) = self.resume {
+			// Whole snapshots only (the arrays may differ after a crash), up to the time resumed from.
+			let (_, times) = read_npy(&self.file("time"))?;
+			let rows = writers.iter().map(|w| w.rows()).min().unwrap_or(0);
+			let rows = times[..rows].iter().take_while(|&&t| t <= resume).count();
+			for w in &mut writers {
+				w.truncate(rows)?;
+			}
+		}
+
+		if !(self.resume.is_some() && self.file("mass").exists()) {
+			let mut mass = NpyWriter::create(&self.file("mass"), &[])?;
+			for &m in masses {
+				mass.push_row(&[m])?;
+			}
+			mass.flush()?;
+		}
+		Ok(writers)
+	}
+}
+
+impl<F: Forces> OutputSink<F> for NpyTrajectory {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		let particles = sim.particles();
+		if self.writers.is_none() {
+			let masses: Vec<f64> = particles.iter().map(|p| p.mass).collect();
+			self.writers = Some(self.open(particles.len(), &masses)?);
+		}
+		let [time, pos, vel] = self.writers.as_mut().unwrap();
+
+		let flatten = |v: &mut dyn Iterator<Item = vec2>| v.flat_map(|v| [v.x, v.y]).collect::<Vec<f64>>();
+		pos.push_row(&flatten(&mut particles.iter().map(|p| p.pos)))?;
+		vel.push_row(&flatten(&mut particles.iter().map(|p| p.vel)))?;
+		time.push_row(&[sim.time()])?;
+		// time last: a snapshot counts once it has a time.
+		for w in [pos, vel, time] {
+			w.flush()?;
+		}
+		Ok(())
+	}
+
+	fn close(&mut self) -> Result<()> {
+		self.writers = None; // flushed on every snapshot, closed on drop.
+		if self.npz && self.file("time").exists() {
+			let arrays: Vec<(&str, PathBuf)> = Self::ARRAYS.iter().map(|&a| (a, self.file(a))).collect();
+			write_npz(&self.dir.join(Self::NPZ_FILE), &arrays)?;
+			for (_, file) in arrays {
+				fs::remove_file(file)?;
+			}
+		}
+		Ok(())
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn tmp_dir(name: &str) -> PathBuf {
+		let dir = std::env::temp_dir().join(format!("astrosim_npy_test_{}_{}", name, std::process::id()));
+		fs::create_dir_all(&dir).unwrap();
+		dir
+	}
+
+	#[test]
+	fn header_format() {
+		let h = header(&[3, 2]).unwrap();
+		assert_eq!(h.len(), HEADER_LEN);
+		assert_eq!(*h.last().unwrap(), b'\n');
+		assert!(String::from_utf8_lossy(&h).contains("'shape': (3, 2), }"));
+		assert!(String::from_utf8_lossy(&header(&[3]).unwrap()).contains("'shape': (3,), }"));
+	}
+
+	#[test]
+	fn malformed_shape() {
+		let dir = tmp_dir("malformed");
+		let file = dir.join("a.npy");
+		let write = |shape: &[usize], values: usize| {
+			let mut bytes = header(shape).unwrap();
+			bytes.resize(bytes.len() + 8 * values, 0);
+			fs::write(&file, bytes).unwrap();
+		};
+
+		write(&[2, 2], 4);
+		assert!(read_npy(&file).is_ok());
+		// truncated, huge and overflowing shapes.
+		for shape in &[vec![2, 2], vec![1 << 40], vec![usize::MAX, 2], vec![1 << 62, 1 << 62]] {
+			write(shape, 3);
+			assert!(read_npy(&file).is_err(), "{:?}", shape);
+			assert!(NpyWriter::append(&file, &shape[1..]).is_err(), "{:?}", shape);
+		}
+	}
+
+	#[test]
+	fn append_roundtrip() {
+		let dir = tmp_dir("append");
+		let file = dir.join("a.npy");
+
+		let mut w = NpyWriter::create(&file, &[2]).unwrap();
+		w.push_row(&[1.0, 2.0]).unwrap();
+		drop(w);
+		let mut w = NpyWriter::append(&file, &[2]).unwrap();
+		assert_eq!(w.rows(), 1);
+		w.push_row(&[3.0, 4.0]).unwrap();
+		assert!(w.push_row(&[5.0]).is_err());
+		drop(w);
+		assert!(NpyWriter::append(&file, &[3]).is_err());
+
+		assert_eq!(read_npy(&file).unwrap(), (vec![2, 2], vec![1.0, 2.0, 3.0, 4.0]));
+		fs::remove_dir_all(&dir).unwrap();
+	}
+
+	// A resumed run appends to the bundled arrays,
+	// replacing those written after the time resumed from.
+	#[test]
+	fn trajectory_npz_resume() {
+		let dir = tmp_dir("npz");
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), //
+			Particle::new(1e-3, vec2(1.0, 0.0), vec2(0.0, 1.0)),
+		];
+		let new_sim = || Stepper::new(particles.clone(), BruteForce::new());
+
+		let run = |sim: &mut Stepper<BruteForce>, outputs: Outputs<BruteForce>, duration: f64| {
+			let mut outputs = outputs.with_npy(Schedule::Interval(0.5), true).unwrap();
+			sim.advance_with_output(duration, &mut outputs).unwrap();
+			outputs.close().unwrap();
+		};
+		// interrupted after t=1.
+		run(&mut new_sim(), Outputs::new(&dir).unwrap(), 1.5);
+		let mut sim = new_sim();
+		let checkpoint = tmp_dir("npz_checkpoint");
+		run(&mut sim, Outputs::new(&checkpoint).unwrap(), 1.0);
+		let outputs = Outputs::resume(&dir, sim.time()).unwrap();
+		run(&mut sim, outputs, 1.0);
+
+		let extracted = tmp_dir("npz_extracted");
+		extract_npz(&dir.join(NpyTrajectory::NPZ_FILE), &extracted).unwrap();
+		assert_eq!(read_npy(&extracted.join("time.npy")).unwrap(), (vec![5], vec![0.0, 0.5, 1.0, 1.5, 2.0]));
+		let (shape, pos) = read_npy(&extracted.join("pos.npy")).unwrap();
+		assert_eq!(shape, vec![5, 2, 2]);
+		assert_eq!(
+			&pos[16..],
+			&[sim.particles()[0].pos.x, sim.particles()[0].pos.y, sim.particles()[1].pos.x, sim.particles()[1].pos.y]
+		);
+		assert_eq!(read_npy(&extracted.join("mass.npy")).unwrap(), (vec![2], vec![1.0, 1e-3]));
+
+		fs::remove_dir_all(&dir).unwrap();
+		fs::remove_dir_all(&checkpoint).unwrap();
+		fs::remove_dir_all(&extracted).unwrap();
+	}
+}
@@ -1,179 +1,688 @@
-/*
-extern crate image;
 use super::prelude::*;
 use std::fs;
 use std::fs::File;
+use std::fs::OpenOptions;
 use std::io::BufWriter;
 use std::io::Write;
 use std::path::{Path, PathBuf};
+use std::sync::mpsc;
+use std::sync::Arc;
+use std::thread;
 
-pub struct Outputs {
-	output_dir: PathBuf,
+/// Destination for simulation output: a file, image sequence, ...
+///
+/// Sinks are registered with `Outputs`, together with a `Schedule`
+/// saying when they should write output. Custom sinks can be added with `Outputs::with_sink`.
+pub trait OutputSink<F: Forces> {
+	/// Called for the initial state and after every time step, regardless of the schedule.
+	/// E.g. to accumulate time averages.
+	fn step(&mut self, _sim: &Stepper<F>) -> Result<()> {
+		Ok(())
+	}
 
-	render_every: f64,
-	render_next: f64,
+	/// Called at the scheduled times (or steps), to write output.
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()>;
 
-	density: Option<Image<f32>>,
-	positions_every: u32,
-	timestep_file: Option<BufWriter<File>>,
-	positions_file: Option<BufWriter<File>>,
+	/// Whether time steps should be truncated to land exactly on the scheduled times.
+	/// If not, output is written at the first step at or past each scheduled time.
+	fn exact_times(&self) -> bool {
+		true
+	}
+
+	/// Called once at the end of the run, e.g. to write accumulated results.
+	fn close(&mut self) -> Result<()> {
+		Ok(())
+	}
 }
 
-impl Outputs {
-	const TIMESTEPS_FILE: &'static str = "timesteps.txt";
-	const POSITIONS_FILE: &'static str = "positions.txt";
-	const DENSITY_FILE: &'static str = "density.png";
+/// A set of output sinks, writing to files in an output directory.
+pub struct Outputs<F: Forces> {
+	output_dir: PathBuf,
+	resume: Option<f64>, // time resumed from, when appending.
+	files: Vec<PathBuf>, // created so far, each written by one sink.
+	sinks: Vec<Scheduled<F>>,
+}
+
+/// A sink with its schedule.
+struct Scheduled<F: Forces> {
+	schedule: Schedule,
+	next: f64, // next scheduled time, NaN until started.
+	sink: Box<dyn OutputSink<F>>,
+}
 
+impl<F: Forces> Outputs<F> {
 	/// Outputs that will write to files in output_dir.
 	/// Individual outputs still need to be enabled. E.g.:
 	///
 	///   let outputs = Outputs::new("some/dir")?
 	///        .with_timesteps(true)?
-	///        .with_positions_every(1)?;
+	///        .with_positions(Schedule::Interval(0.1))?;
 	///
 	pub fn new<P: AsRef<Path>>(output_dir: P) -> Result<Self> {
 		fs::create_dir_all(&output_dir)?;
 
 		Ok(Self {
-			render_every: 0.0, // TODO
-			positions_every: 0,
 			output_dir: output_dir.as_ref().into(),
-			timestep_file: None,
-			positions_file: None,
-			density: None,
-			render_next: 0.0,
+			resume: None,
+			files: Vec::new(),
+			sinks: Vec::new(),
 		})
 	}
 
+	/// Like new, but appends to existing files instead of overwriting them.
+	/// E.g. to continue a run resumed from a checkpoint at `time`:
+	/// outputs after `time` (written after the checkpoint) are discarded.
+	pub fn resume<P: AsRef<Path>>(output_dir: P, time: f64) -> Result<Self> {
+		let mut outputs = Self::new(output_dir)?;
+		outputs.resume = Some(time);
+		Ok(outputs)
+	}
+
+	pub fn output_dir(&self) -> &Path {
+		&self.output_dir
+	}
+
+	/// Registers an arbitrary sink, writing output on the given schedule.
+	pub fn with_sink(mut self, schedule: Schedule, sink: Box<dyn OutputSink<F>>) -> Self {
+		self.sinks.push(Scheduled { schedule, next: f64::NAN, sink });
+		self
+	}
+
 	/// Enables writing timesteps.txt file to output directory.
 	pub fn with_timesteps(mut self, enabled: bool) -> Result<Self> {
-		self.timestep_file = if enabled {
-			let mut f = self.create(Self::TIMESTEPS_FILE)?;
-			writeln!(f, "# time dt error")?;
-			Some(f)
-		} else {
-			None
-		};
-		Ok(self)
+		if !enabled {
+			return Ok(self);
+		}
+		let sink = TimestepsFile::new(self.create(TimestepsFile::FILE)?)?;
+		Ok(self.with_sink(Schedule::every_step(), Box::new(sink)))
 	}
 
-	/// Enables writing positions.txt files to output directory every `every`th time step.
-	pub fn with_positions_every(mut self, every: u32) -> Result<Self> {
-		self.positions_file = if every != 0 {
-			let mut f = self.create(Self::POSITIONS_FILE)?;
-			writeln!(f, "# time position_x position_y ...")?;
-			Some(f)
-		} else {
-			None
-		};
-		Ok(self)
+	/// Enables writing particle positions to positions.txt in the output directory.
+	pub fn with_positions(mut self, schedule: Schedule) -> Result<Self> {
+		let sink = PositionsFile::new(self.create(PositionsFile::FILE)?);
+		Ok(self.with_sink(schedule, Box::new(sink)))
+	}
+
+	/// Enables writing positions.txt every `every`th time step (if not 0).
+	pub fn with_positions_every(self, every: u32) -> Result<Self> {
+		if every == 0 {
+			return Ok(self);
+		}
+		self.with_positions(Schedule::Steps(every as u64))
+	}
+
+	/// Enables writing density images of `viewport`, of size w x h pixels,
+	/// each averaged over the time since the previous one.
+	pub fn with_density(self, w: u32, h: u32, viewport: Viewport, schedule: Schedule) -> Result<Self> {
+		if w == 0 || h == 0 {
+			return Ok(self);
+		}
+		let sink = DensityFrames::new(&self.output_dir, w, h, viewport);
+		Ok(self.with_sink(schedule, Box::new(sink)))
 	}
 
-	/// Enables writing a time-averaged density image of size pixels x pixels.
-	pub fn with_density(mut self, pixels: u32) -> Result<Self> {
-		self.density = if pixels != 0 { Some(Image::new(pixels, pixels)) } else { None };
-		Ok(self)
+	/// Enables writing time, positions and velocities as NumPy arrays,
+	/// optionally bundled into a single .npz file at the end of the run.
+	pub fn with_npy(self, schedule: Schedule, npz: bool) -> Result<Self> {
+		let sink = NpyTrajectory::new(&self.output_dir, self.resume, npz)?;
+		Ok(self.with_sink(schedule, Box::new(sink)))
 	}
 
+	/// Enables writing snapshots to a single multi-snapshot file, snapshots.bin (see `SnapshotReader`).
+	pub fn with_snapshots(self, schedule: Schedule, units: &Units) -> Result<Self> {
+		let sink = SnapshotFile::new(&self.output_dir.join(SnapshotFile::FILE), self.resume, units)?;
+		Ok(self.with_sink(schedule, Box::new(sink)))
+	}
+
+	/// Like `with_density`, for density frames configured with
+	/// a tone map, animation, etc. E.g.:
+	///
+	///   let frames = DensityFrames::new(outputs.output_dir(), 512, 512, Viewport::from_scale(2.0)).with_tone_map(ToneMap::new().with_colormap(Colormap::Magma));
+	///   let outputs = outputs.with_density_frames(Schedule::Interval(0.1), frames);
 	///
-	pub fn with_render_every(mut self, every: f64) -> Result<Self> {
-		self.render_every = every;
-		Ok(self)
+	pub fn with_density_frames(self, schedule: Schedule, frames: DensityFrames) -> Self {
+		self.with_sink(schedule, Box::new(frames))
 	}
 
-	pub fn close(self) -> Result<()> {
-		//Self::render_density_file(&self.denSelf::DENSITY_FILE)?;
-		// TODO: set everything to None, implement close on drop?
-		Ok(())
+	/// Like `with_density`, but adds all frames to `animation` (of the frame size)
+	/// instead of writing individual images.
+	pub fn with_density_animation(self, viewport: Viewport, schedule: Schedule, animation: Animation) -> Self {
+		let (w, h) = animation.size();
+		let frames = DensityFrames::new(&self.output_dir, w, h, viewport).with_animation(animation);
+		self.with_density_frames(schedule, frames)
+	}
+
+	/// Enables writing JSON Lines to stdout (see `JsonLines`),
+	/// optionally with particle states and diagnostics.
+	pub fn with_json_lines(self, schedule: Schedule, particles: bool, diagnostics: bool) -> Self {
+		let sink = JsonLines::new(std::io::stdout()).with_particles(particles).with_diagnostics(diagnostics);
+		self.with_sink(schedule, Box::new(sink))
+	}
+
+	/// Enables accumulating a histogram of the orbital elements of `selection` around the body with index `primary`,
+	/// each step weighted by `exposure`, written to histogram.csv (and histogram.png for 2D histograms).
+	pub fn with_histogram(self, schedule: Schedule, hist: Histogram, primary: usize, selection: Selection, exposure: Exposure) -> Self {
+		let sink = HistogramFile::new(&self.output_dir, hist, primary, selection).with_exposure(exposure);
+		self.with_sink(schedule, Box::new(sink))
+	}
+
+	/// Enables writing SVG plots of the particles to snapshot_<step>.svg.
+	pub fn with_svg(self, schedule: Schedule, plot: SvgPlot) -> Self {
+		let sink = SvgFrames::new(&self.output_dir, plot);
+		self.with_sink(schedule, Box::new(sink))
+	}
+
+	/// Enables writing energy, momentum and their drift to diagnostics.txt.
+	pub fn with_diagnostics(mut self, schedule: Schedule) -> Result<Self> {
+		let sink = DiagnosticsFile::new(self.create(DiagnosticsFile::FILE)?)?;
+		Ok(self.with_sink(schedule, Box::new(sink)))
+	}
+
+	/// To be called before advancing the simulation:
+	/// schedules the first outputs after the current time
+	/// (or at the current time, for the initial state).
+	pub fn start(&mut self, sim: &Stepper<F>) {
+		for s in &mut self.sinks {
+			if s.next.is_nan() {
+				s.next = s.schedule.next_time(sim.time(), sim.step_count() != 0);
+			}
+		}
 	}
 
-	/// To be called after every simulation time step
-	/// to write all configured outputs.
-	pub fn output(&mut self, sim: &Stepper) -> Result<()> {
-		self.output_timesteps(sim)?;
-		self.output_positions(sim)?;
-		self.accumulate_density(sim);
-		self.render(sim)?;
+	/// Earliest scheduled time at or after `time` that should be landed on exactly.
+	/// (At `time` only for the initial state, which is output before any step is taken).
+	pub fn next_time(&self, time: f64) -> f64 {
+		self.sinks.iter().filter(|s| s.sink.exact_times() && s.next >= time).map(|s| s.next).fold(INF, f64::min)
+	}
+
+	/// Like `next_time`, but strictly after `time`: when advancing continues after output at `time`.
+	pub fn next_time_after(&self, time: f64) -> f64 {
+		self.sinks
+			.iter()
+			.filter(|s| s.sink.exact_times())
+			.map(|s| if s.next > time { s.next } else { s.schedule.next_time(time, true) })
+			.fold(INF, f64::min)
+	}
+
+	/// To be called for the initial state and after every simulation time step
+	/// to write all outputs that are due.
+	pub fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		self.start(sim);
+		for s in &mut self.sinks {
+			s.sink.step(sim)?;
+			if s.schedule.is_step_due(sim.step_count()) || sim.time() >= s.next {
+				s.sink.output(sim)?;
+				s.next = s.schedule.next_time(sim.time(), true);
+			}
+		}
 		Ok(())
 	}
 
-	fn accumulate_density(&mut self, sim: &Stepper) {
-		if let Some(img) = self.density.as_mut() {
-			let scale = 2.0; // TODO
-				 // TODO: dt is wrong, is for next step should be for current
-			accumulate_density(img, &sim.particles()[1..], scale, sim.dt() as f32)
+	pub fn close(mut self) -> Result<()> {
+		for s in &mut self.sinks {
+			s.sink.close()?;
 		}
+		Ok(())
 	}
 
-	fn render(&mut self, sim: &Stepper) -> Result<()> {
-		if let Some(img) = self.density.as_mut() {
-			if self.render_every != 0.0 && sim.time() % self.render_every <= sim.dt {
-				if sim.time() >= self.render_next {
-					let file = self.output_dir.join(&format!("density_{:09}.png", sim.step_count()));
-					Self::render_density_file(&img, &file)?;
-					for p in img.pixels_mut() {
-						*p = 0.0
-					}
-					self.render_next += self.render_every;
+	/// Create a file in the output directory
+	/// (or open for appending, when resuming).
+	/// Each file can only be created once, for one sink.
+	pub fn create(&mut self, basename: &str) -> Result<OutputFile> {
+		let name = self.output_dir.join(basename);
+		let msg = format!("create {}", name.to_string_lossy());
+		if self.files.contains(&name) {
+			return err(format!("{}: already written by another output", &msg));
+		}
+		self.files.push(name.clone());
+		let exists = self.resume.is_some() && name.exists();
+		let f = match self.resume {
+			Some(time) => {
+				if exists {
+					truncate_rows_after(&name, time).msg(&msg)?;
 				}
+				OpenOptions::new().append(true).create(true).open(&name).msg(&msg)?
 			}
+			None => File::create(&name).msg(&msg)?,
+		};
+		Ok(OutputFile {
+			out: BufWriter::new(f),
+			appending: exists,
+		})
+	}
+}
+
+// Discard the rows of a text output (time in the first column) after `time`,
+// and a partially written row at the end. Comment lines are kept.
+fn truncate_rows_after(file: &Path, time: f64) -> std::io::Result<()> {
+	let text = fs::read_to_string(file)?;
+	let mut len = 0;
+	for line in text.split_inclusive('\n') {
+		let t = line.split_whitespace().next().and_then(|t| t.parse::<f64>().ok());
+		if !line.ends_with('\n') || t.is_some_and(|t| t > time) {
+			break;
+		}
+		len += line.len();
+	}
+	OpenOptions::new().write(true).open(file)?.set_len(len as u64)?;
+	Ok(())
+}
+
+/// A (buffered) text file in the output directory.
+pub struct OutputFile {
+	out: BufWriter<File>,
+	appending: bool,
+}
+
+impl OutputFile {
+	/// Write a header line, unless appending to an existing file
+	/// (which already has one).
+	pub fn header(&mut self, header: &str) -> Result<()> {
+		if !self.appending {
+			writeln!(self.out, "{}", header)?;
+			self.appending = true;
 		}
 		Ok(())
 	}
+}
 
-	//fn render_positions(&mut self, particles: &[Particle], pixels: u32, scale: f64, i: u32) -> Result<()> {
-	//	let path = format!("output/{:06}.png", i);
+impl Write for OutputFile {
+	fn write(&mut self, buf: &[u8]) -> std::io::Result<usize> {
+		self.out.write(buf)
+	}
 
-	//	let img_data = render::render(particles, pixels, scale);
-	//	let img = image::ImageBuffer::from_fn(pixels, pixels, |x, y| {
-	//		let v = img_data[y as usize][x as usize];
-	//		let v = if v == 0.0 { 0u8 } else { 255u8 };
-	//		image::Rgba([v, v, v, 255])
-	//	});
+	fn flush(&mut self) -> std::io::Result<()> {
+		self.out.flush()
+	}
+}
 
-	//	Ok(img.save(&path)?)
-	//}
+/// Writes time, time step and error estimate of each step.
+pub struct TimestepsFile {
+	out: OutputFile,
+}
 
-	fn render_density_file(density: &Image<f32>, file: &Path) -> Result<()> {
-		let max = density.pixels().iter().fold(0.0, |a, b| f32::max(a, *b));
-		let (w, h) = density.dimensions();
-		let img = image::ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
-			let density = density[y as usize][x as usize];
-			let v = ((density / max).sqrt() * 255.0) as u8;
-			image::Rgba([v, v, v, 255])
-		});
-		img.save(file)?;
+impl TimestepsFile {
+	pub const FILE: &'static str = "timesteps.txt";
+
+	pub fn new(mut out: OutputFile) -> Result<Self> {
+		out.header("# time dt error")?;
+		Ok(Self { out })
+	}
+}
+
+impl<F: Forces> OutputSink<F> for TimestepsFile {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		let w = &mut self.out;
+		writeln!(w, "{}\t{}\t{}", sim.time(), sim.dt(), sim.relative_error())?;
+		w.flush()?;
+		Ok(())
+	}
+}
+
+/// Writes all particle positions.
+pub struct PositionsFile {
+	out: OutputFile,
+}
+
+impl PositionsFile {
+	pub const FILE: &'static str = "positions.txt";
+
+	pub fn new(out: OutputFile) -> Self {
+		Self { out }
+	}
+}
+
+impl<F: Forces> OutputSink<F> for PositionsFile {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		let w = &mut self.out;
+
+		// Header with particle names, known only once we see the particles.
+		let mut header = String::from("# time");
+		for (i, m) in sim.meta().iter().enumerate() {
+			let label = m.label(i);
+			header += &format!(" {}.x {}.y", label, label);
+		}
+		w.header(&header)?;
+
+		write!(w, "{}", sim.time())?;
+		for p in sim.particles() {
+			write!(w, " {} {}", p.pos.x, p.pos.y)?;
+		}
+		writeln!(w)?;
+		w.flush()?;
 		Ok(())
 	}
+}
+
+/// Accumulates particle density after every step,
+/// and saves it as an image (or animation frame) when output is scheduled.
+/// With a raw format (PFM, FITS) as well as an animation, both are written.
+pub struct DensityFrames {
+	img: Image<f32>,
+	viewport: Viewport,
+	frame: ReferenceFrame,
+	polar: Option<(Polar, usize)>,
+	// Particles to render, None until resolved to the default.
+	selection: Option<Selection>,
+	kernel: Kernel,
+	splat: ParallelSplat,
+	exposure: Exposure,
+	overlays: Overlays,
+	// Names for the overlays, copied once.
+	meta: Option<Arc<[Meta]>>,
+	trails: Option<Trails>,
+	background: bool,
+	// Writes frames, here or on the background thread (then None here).
+	writer: Option<FrameWriter>,
+	thread: Option<RenderThread>,
+}
+
+// Background thread writing frames: the queue of frames to write, and its result.
+type RenderThread = (mpsc::SyncSender<Frame>, thread::JoinHandle<std::result::Result<(), String>>);
+
+// Tone-maps, annotates and saves accumulated density.
+struct FrameWriter {
+	dir: PathBuf,
+	tone_map: ToneMap,
+	format: DensityFormat,
+	animation: Option<Animation>,
+}
+
+// Everything needed to write one frame, copied so that it can be written in the background.
+struct Frame {
+	density: Image<f32>,
+	time: f64,
+	step_count: u64,
+	projection: Box<dyn Projection + Send + Sync>,
+	// The world viewport, if not in polar projection.
+	viewport: Option<Viewport>,
+	overlays: Overlays,
+	// Only if needed for the overlays.
+	particles: Vec<Particle>,
+	meta: Option<Arc<[Meta]>>,
+	trails: Option<Trails>,
+}
+
+impl DensityFrames {
+	pub fn new(dir: &Path, w: u32, h: u32, viewport: Viewport) -> Self {
+		Self {
+			img: Image::new(w, h),
+			viewport,
+			frame: ReferenceFrame::default(),
+			polar: None,
+			selection: None,
+			kernel: Kernel::default(),
+			splat: ParallelSplat::new(1),
+			exposure: Exposure::new(),
+			overlays: Overlays::default(),
+			meta: None,
+			trails: None,
+			background: false,
+			writer: Some(FrameWriter {
+				dir: dir.into(),
+				tone_map: ToneMap::default(),
+				format: DensityFormat::default(),
+				animation: None,
+			}),
+			thread: None,
+		}
+	}
+
+	/// Render in this (e.g. co-rotating) frame. The viewport is relative to the frame.
+	pub fn with_frame(mut self, frame: ReferenceFrame) -> Self {
+		self.frame = frame;
+		self
+	}
+
+	/// Render in polar projection around the body with index `center`, instead of the viewport.
+	/// In a rotating frame, azimuth is relative to the frame.
+	pub fn with_polar(mut self, polar: Polar, center: usize) -> Self {
+		self.polar = Some((polar, center));
+		self
+	}
+
+	/// Render only these particles (default: all but the most massive body, e.g. the sun).
+	pub fn with_selection(mut self, selection: Selection) -> Self {
+		self.selection = Some(selection);
+		self
+	}
+
+	/// Deposit particles with this kernel (default: nearest pixel).
+	pub fn with_kernel(mut self, kernel: Kernel) -> Self {
+		self.kernel = kernel;
+		self
+	}
+
+	/// Accumulate density on this many threads (0: all available cores), see `ParallelSplat`.
+	pub fn with_threads(mut self, threads: usize) -> Self {
+		self.splat = ParallelSplat::new(threads);
+		self
+	}
+
+	/// Weight each step by the elapsed time like this (default: rectangle rule).
+	pub fn with_exposure(mut self, exposure: Exposure) -> Self {
+		self.exposure = exposure;
+		self
+	}
+
+	/// Tone-map, annotate and save frames on a background thread, overlapping with the simulation.
+	/// Errors writing frames are returned by a later output, or on close.
+	pub fn with_background(mut self, background: bool) -> Self {
+		self.background = background;
+		self
+	}
+
+	pub fn with_tone_map(mut self, tone_map: ToneMap) -> Self {
+		self.writer().tone_map = tone_map;
+		self
+	}
+
+	/// Stamp these overlays (time, scale bar, ...) onto each frame.
+	pub fn with_overlays(mut self, overlays: Overlays) -> Self {
+		self.overlays = overlays;
+		self
+	}
 
-	fn output_positions<F: Forces>(&mut self, sim: &Stepper<F>) -> Result<()> {
-		if self.positions_every != 0 && sim.step_count() % (self.positions_every as u64) == 0 {
-			if let Some(w) = self.positions_file.as_mut() {
-				write!(w, "{}", sim.time())?;
-				for p in sim.particles() {
-					write!(w, " {} {}", p.pos.x, p.pos.y)?;
+	/// Draw trails of recent positions on each frame, recorded after every step.
+	pub fn with_trails(mut self, trails: Trails) -> Self {
+		self.trails = Some(trails);
+		self
+	}
+
+	/// Write frames in this format (default: 8-bit PNG).
+	/// Trails and overlays are only drawn on 8-bit images (PNG or animation frames).
+	pub fn with_format(mut self, format: DensityFormat) -> Self {
+		self.writer().format = format;
+		self
+	}
+
+	/// Add frames to an animation instead of writing individual images.
+	/// (The animation must have the same size as the frames).
+	pub fn with_animation(mut self, animation: Animation) -> Self {
+		self.writer().animation = Some(animation);
+		self
+	}
+
+	// Writer settings, before frames are written.
+	fn writer(&mut self) -> &mut FrameWriter {
+		self.writer.as_mut().expect("DensityFrames: configure before writing frames")
+	}
+
+	// Deposit the selected particles, by default all but the most massive (looked up once).
+	fn accumulate(&mut self, particles: &[Particle], weight: f32) {
+		if particles.is_empty() {
+			return;
+		}
+		let projection = self.projection(particles);
+		let selection = self.selection.get_or_insert_with(|| {
+			let primary = most_massive(particles);
+			Selection::Mask((0..particles.len()).map(|i| Some(i) != primary).collect())
+		});
+		self.splat.splat(&mut self.img, particles, selection, projection.as_ref(), self.kernel, weight);
+	}
+
+	// Projection for the current particle positions: the viewport in the reference frame, or polar.
+	fn projection(&self, particles: &[Particle]) -> Box<dyn Projection + Send + Sync> {
+		match &self.polar {
+			None => Box::new(self.frame.viewport(particles, &self.viewport)),
+			Some((polar, center)) => {
+				let rotation = polar.rotation + self.frame.angle(particles);
+				Box::new(polar.clone().around(&particles[*center]).with_rotation(rotation))
+			}
+		}
+	}
+
+	// Wait for the background thread to write all frames, returning its error if any.
+	fn join(&mut self) -> Result<()> {
+		match self.thread.take() {
+			None => Ok(()),
+			Some((sender, handle)) => {
+				drop(sender);
+				match handle.join() {
+					Ok(result) => result.map_err(error),
+					Err(_) => err("density frames: render thread panicked"),
 				}
-				writeln!(w)?;
-				w.flush()?;
+			}
+		}
+	}
+}
+
+impl FrameWriter {
+	fn write(&mut self, frame: &Frame) -> Result<()> {
+		let file = self.dir.join(format!("density_{:09}.{}", frame.step_count, self.format.extension()));
+		let density = &frame.density;
+		if self.animation.is_some() || self.format == DensityFormat::Png {
+			let mut img = self.tone_map.apply(density);
+			if let Some(trails) = &frame.trails {
+				trails.draw(&mut img);
+			}
+			frame
+				.overlays
+				.draw(&mut img, frame.time, &frame.particles, frame.meta.as_deref().unwrap_or(&[]), frame.projection.as_ref());
+			match &mut self.animation {
+				Some(anim) => anim.add_frame(&img)?,
+				None => img.save(&file)?,
+			}
+		}
+		match self.format {
+			DensityFormat::Png => (),
+			DensityFormat::Png16 => save_png16(&self.tone_map.normalize(density), &file)?,
+			DensityFormat::Pfm => save_pfm(density, &file)?,
+			DensityFormat::Fits => {
+				let mut header = FitsHeader::new().with_time(frame.time).with("STEPS", frame.step_count as i64, "time steps");
+				if let Some(viewport) = &frame.viewport {
+					header = header.with_viewport(viewport, density.width(), density.height());
+				}
+				save_fits(density, &header, &file)?
 			}
 		}
 		Ok(())
 	}
 
-	fn output_timesteps<F: Forces>(&mut self, sim: &Stepper<F>) -> Result<()> {
-		if let Some(w) = self.timestep_file.as_mut() {
-			writeln!(w, "{}\t{}\t{}", sim.time(), sim.dt(), sim.relative_error())?;
-			w.flush()?;
+	fn finish(self) -> Result<()> {
+		match self.animation {
+			Some(anim) => anim.finish(),
+			None => Ok(()),
+		}
+	}
+}
+
+impl<F: Forces> OutputSink<F> for DensityFrames {
+	fn step(&mut self, sim: &Stepper<F>) -> Result<()> {
+		if let Some(weight) = self.exposure.add(sim.time(), sim.next_dt()) {
+			self.accumulate(sim.particles(), weight);
+		}
+		if self.trails.is_some() {
+			let projection = self.projection(sim.particles());
+			let (w, h) = (self.img.width(), self.img.height());
+			if let Some(trails) = &mut self.trails {
+				trails.record(sim.particles(), projection.as_ref(), w, h);
+			}
 		}
 		Ok(())
 	}
 
-	// Create a file in the output directory.
-	fn create(&self, basename: &str) -> Result<BufWriter<File>> {
-		let name = self.output_dir.join(basename);
-		let f = File::create(&name).msg(&format!("create {}", name.to_string_lossy()))?;
-		let buf = BufWriter::new(f);
-		Ok(buf)
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		// The initial state has not been exposed for any time yet.
+		if sim.step_count() == 0 {
+			return Ok(());
+		}
+		let particles = sim.particles();
+		if !self.overlays.is_empty() && self.meta.is_none() {
+			self.meta = Some(sim.meta().into());
+		}
+		self.splat.add_to(&mut self.img);
+		self.splat.clear();
+		let (w, h) = (self.img.width() as u32, self.img.height() as u32);
+		let frame = Frame {
+			density: std::mem::replace(&mut self.img, Image::new(w, h)),
+			time: sim.time(),
+			step_count: sim.step_count(),
+			projection: self.projection(particles),
+			viewport: if self.polar.is_none() { Some(self.frame.viewport(particles, &self.viewport)) } else { None },
+			overlays: self.overlays.clone(),
+			particles: if self.overlays.is_empty() { Vec::new() } else { particles.to_vec() },
+			meta: self.meta.clone(),
+			trails: self.trails.clone(),
+		};
+
+		if !self.background {
+			return self.writer().write(&frame);
+		}
+		if self.thread.is_none() {
+			let mut writer = self.writer.take().expect("DensityFrames: writer");
+			// A frame in the queue while the next is accumulated (each is a copy of the image).
+			let (sender, receiver) = mpsc::sync_channel::<Frame>(1);
+			let handle = thread::spawn(move || {
+				for frame in receiver {
+					writer.write(&frame).map_err(|e| e.to_string())?;
+				}
+				writer.finish().map_err(|e| e.to_string())
+			});
+			self.thread = Some((sender, handle));
+		}
+		let sent = self.thread.as_ref().map(|(sender, _)| sender.send(frame).is_ok());
+		if sent != Some(true) {
+			// The thread stopped on an error.
+			self.join()?;
+		}
+		Ok(())
+	}
+
+	fn close(&mut self) -> Result<()> {
+		self.join()?;
+		match self.writer.take() {
+			Some(writer) => writer.finish(),
+			None => Ok(()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn density_selection() {
+		let dir = std::env::temp_dir();
+		let particles = vec![
+			Particle::new(1e-3, vec2(0.5, 0.0), vec2(0.0, 1.0)), // planet
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),  // sun, not first
+			Particle::new(0.0, vec2(-0.5, 0.0), vec2(0.0, 1.0)),
+		];
+
+		// by default, all but the most massive body.
+		let mut frames = DensityFrames::new(&dir, 8, 8, Viewport::from_scale(1.0));
+		frames.accumulate(&particles, 1.0);
+		assert_eq!(frames.img.pixels().iter().sum::<f32>(), 2.0);
+		assert_eq!(frames.img[4][4], 0.0);
+
+		let mut frames = DensityFrames::new(&dir, 8, 8, Viewport::from_scale(1.0)).with_selection(Selection::range(1..2));
+		frames.accumulate(&particles, 1.0);
+		assert_eq!(frames.img[4][4], 1.0);
+
+		let mut frames = DensityFrames::new(&dir, 8, 8, Viewport::from_scale(1.0));
+		frames.accumulate(&[], 1.0);
+		assert_eq!(frames.img.pixels().iter().sum::<f32>(), 0.0);
 	}
 }
-*/
@@ -0,0 +1,202 @@
+use super::prelude::*;
+use std::str::FromStr;
+
+/// Annotation stamped onto rendered frames.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Overlay {
+	/// Simulation time: "t = 12.50".
+	Time,
+	/// Number of particles: "N = 2002".
+	ParticleCount,
+	/// Fixed text.
+	Label(String),
+	/// Scale bar of this length in world units, labelled with the length.
+	ScaleBar(f64),
+	/// Crosshair on the body with this name.
+	Crosshair(String),
+	/// Circle with center and radius in world units (e.g. a reference orbit).
+	Circle(vec2, f64),
+	/// Osculating Keplerian orbit of the body with this name,
+	/// around the second body (default: the most massive other body).
+	Orbit(String, Option<String>),
+}
+
+/// Overlays drawn on each frame, in one colour.
+/// Text (time, count, labels) is stacked in the top-left corner, the scale bar is in the bottom-left.
+///
+///   let overlays = Overlays::new().with(Overlay::Time).with(Overlay::ParticleCount).with(Overlay::ScaleBar(1.0));
+///   overlays.draw(&mut frame, sim.time(), sim.particles(), sim.meta(), &viewport);
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Overlays {
+	pub items: Vec<Overlay>,
+	pub color: [u8; 4],
+	/// Magnification of the 5x7 pixel font.
+	pub text_scale: u32,
+}
+
+impl Default for Overlays {
+	fn default() -> Self {
+		Self {
+			items: Vec::new(),
+			color: [255, 255, 255, 255],
+			text_scale: 1,
+		}
+	}
+}
+
+impl Overlays {
+	/// No overlays yet, in white.
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with(mut self, overlay: Overlay) -> Self {
+		self.items.push(overlay);
+		self
+	}
+
+	pub fn with_color(mut self, color: [u8; 4]) -> Self {
+		self.color = color;
+		self
+	}
+
+	pub fn with_text_scale(mut self, scale: u32) -> Self {
+		self.text_scale = scale;
+		self
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.items.is_empty()
+	}
+
+	/// Draw the overlays on a frame rendered with `projection`, at `time`.
+	/// Bodies are found by name in `meta` (indexed like the particles).
+	pub fn draw<P: Projection + ?Sized>(&self, img: &mut image::RgbaImage, time: f64, particles: &[Particle], meta: &[Meta], projection: &P) {
+		let c = image::Rgba(self.color);
+		let s = self.text_scale;
+		let margin = 4 * s as i32;
+		let (w, h) = (img.width() as usize, img.height() as usize);
+		let pixel = |pos: vec2| projection.project(&Particle::new(0.0, pos, vec2::ZERO), w, h);
+		let mut text_y = margin;
+		let mut text = |img: &mut image::RgbaImage, line: &str| {
+			img.draw_text(margin, text_y, line, s, c);
+			text_y += LINE_HEIGHT * s as i32;
+		};
+
+		for item in &self.items {
+			match item {
+				Overlay::Time => text(img, &format!("t = {:.2}", time)),
+				Overlay::ParticleCount => text(img, &format!("N = {}", particles.len())),
+				Overlay::Label(label) => text(img, label),
+				Overlay::ScaleBar(length) => {
+					let len = length * projection.pixels_per_unit(w, h).0;
+					let y = (h as i32 - margin) as f64 - 0.5;
+					let (x0, x1) = (margin as f64 + 0.5, margin as f64 + 0.5 + len);
+					img.draw_line(vec2(x0, y), vec2(x1, y), c);
+					img.draw_line(vec2(x0, y), vec2(x0, y - 2.0 * s as f64), c);
+					img.draw_line(vec2(x1, y), vec2(x1, y - 2.0 * s as f64), c);
+					let label_y = y as i32 - (LINE_HEIGHT + 1) * s as i32;
+					img.draw_text(margin + 2 * s as i32, label_y, &format!("{}", length), s, c);
+				}
+				Overlay::Crosshair(name) => {
+					if let Some(i) = index_of(meta, name) {
+						let size = 4.0 * s as f64;
+						img.draw_crosshair(projection.project(&particles[i], w, h), size, size / 2.0, c);
+					}
+				}
+				Overlay::Circle(center, radius) => {
+					img.draw_circle(pixel(*center), radius * projection.pixels_per_unit(w, h).0, c);
+				}
+				Overlay::Orbit(name, primary) => {
+					let body = match index_of(meta, name) {
+						Some(i) => i,
+						None => continue,
+					};
+					let primary = match primary {
+						Some(primary) => index_of(meta, primary),
+						None => (0..particles.len()).filter(|&i| i != body).max_by(|&i, &j| particles[i].mass.total_cmp(&particles[j].mass)),
+					};
+					if let Some(primary) = primary {
+						let rgb = [self.color[0], self.color[1], self.color[2]];
+						draw_orbit(img, &particles[body], &particles[primary], projection, rgb, self.color[3] as f32 / 255.0);
+					}
+				}
+			}
+		}
+	}
+}
+
+impl FromStr for Overlay {
+	type Err = Error;
+
+	/// "time", "count", "label:TEXT", "scalebar:LENGTH", "crosshair:NAME", "circle:X:Y:RADIUS" or "orbit:NAME[:PRIMARY]".
+	fn from_str(s: &str) -> Result<Self> {
+		let msg = || {
+			format!(
+				"unknown overlay {:?}, want time, count, label:TEXT, scalebar:LENGTH, crosshair:NAME, circle:X:Y:RADIUS or orbit:NAME[:PRIMARY]",
+				s
+			)
+		};
+		let num = |v: &str| v.parse::<f64>().map_err(|_| error(msg()));
+		let (kind, arg) = match s.find(':') {
+			Some(i) => (&s[..i], Some(&s[i + 1..])),
+			None => (s, None),
+		};
+		match (kind, arg) {
+			("time", None) => Ok(Overlay::Time),
+			("count", None) => Ok(Overlay::ParticleCount),
+			("label", Some(text)) => Ok(Overlay::Label(text.into())),
+			("scalebar", Some(len)) => Ok(Overlay::ScaleBar(num(len)?)),
+			("crosshair", Some(name)) => Ok(Overlay::Crosshair(name.into())),
+			("circle", Some(args)) => match args.split(':').collect::<Vec<_>>()[..] {
+				[x, y, r] => Ok(Overlay::Circle(vec2(num(x)?, num(y)?), num(r)?)),
+				_ => err(msg()),
+			},
+			("orbit", Some(args)) => match args.split(':').collect::<Vec<_>>()[..] {
+				[name] => Ok(Overlay::Orbit(name.into(), None)),
+				[name, primary] => Ok(Overlay::Orbit(name.into(), Some(primary.into()))),
+				_ => err(msg()),
+			},
+			_ => err(msg()),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn overlays() {
+		let particles = vec![Particle::new(1.0, vec2(0.48, 0.48), vec2(0.0, 0.0))];
+		let meta = vec![Meta::new().with_name("sun")];
+		let viewport = Viewport::from_scale(2.0);
+		let overlays = Overlays::new()
+			.with("time".parse().unwrap())
+			.with("count".parse().unwrap())
+			.with("scalebar:1".parse().unwrap())
+			.with("crosshair:sun".parse().unwrap())
+			.with("circle:0:0:1.5".parse().unwrap());
+		let mut img = image::RgbaImage::new(100, 100);
+		overlays.draw(&mut img, 1.5, &particles, &meta, &viewport);
+
+		let set = |x, y| img.get_pixel(x, y).0 == [255, 255, 255, 255];
+		let count_set = |x0, y0, x1, y1| (y0..y1).flat_map(|y| (x0..x1).map(move |x| (x, y))).filter(|&(x, y)| set(x, y)).count();
+		// text, two lines.
+		assert!(count_set(0, 0, 60, 24) > 20);
+		assert!(count_set(0, 14, 60, 24) > 10);
+
+		// scale bar: 1 world unit is 25 pixels.
+		assert!(set(4, 95) && set(29, 95) && !set(31, 95));
+		// crosshair around the sun at pixel (62.5, 62.5), with a gap.
+		assert!(set(62, 58) && set(67, 62) && !set(62, 62));
+		// circle of radius 37.5 pixels around (50.5, 50.5).
+		assert!(set(88, 50) || set(87, 50));
+
+		assert!("box".parse::<Overlay>().is_err());
+		assert!("scalebar:x".parse::<Overlay>().is_err());
+		assert_eq!("label:a b".parse::<Overlay>().unwrap(), Overlay::Label("a b".into()));
+		assert_eq!("orbit:jupiter:sun".parse::<Overlay>().unwrap(), Overlay::Orbit("jupiter".into(), Some("sun".into())));
+	}
+}
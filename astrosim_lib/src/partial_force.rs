@@ -8,6 +8,10 @@ impl PartialForce {
 	pub fn new(cutoff_index: usize) -> Self {
 		PartialForce { cutoff_index }
 	}
+
+	pub fn cutoff_index(&self) -> usize {
+		self.cutoff_index
+	}
 }
 
 impl Forces for PartialForce {
@@ -32,4 +36,14 @@ impl Forces for PartialForce {
 			acc[i] += acci;
 		}
 	}
+
+	fn potential_energy(&self, particles: &[Particle]) -> f64 {
+		let mut energy = 0.0;
+		for (i, pi) in particles[..self.cutoff_index].iter().enumerate() {
+			for pj in &particles[(i + 1)..] {
+				energy -= pi.mass * pj.mass / (pj.pos - pi.pos).len();
+			}
+		}
+		energy
+	}
 }
@@ -11,6 +11,7 @@ impl Particle {
 	pub fn new(mass: f64, pos: vec2, vel: vec2) -> Self {
 		Self { mass, pos, vel }
 	}
+
 	pub fn random() -> Self {
 		let mass = 1.0;
 		let pos = vec2(2.0, 3.0);
@@ -19,6 +20,74 @@ impl Particle {
 	}
 }
 
+/// Descriptive data of a particle: name, tag and radius.
+///
+/// Kept in a separate list, indexed like the particles (see `Stepper::meta`),
+/// so that `Particle` stays small and cheap to copy in the force loop.
+/// Look names and tags up once (`index_of`, `Selection::tag`), not per step.
+#[derive(Clone, Debug, Default, PartialEq)]
+pub struct Meta {
+	/// Optional name (or ID), e.g. "jupiter".
+	pub name: Option<String>,
+	/// Optional group tag, e.g. "asteroid".
+	pub tag: Option<String>,
+	/// Physical radius. Does not affect the dynamics (only used for presentation).
+	pub radius: f64,
+}
+
+impl Meta {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	/// Builder-style setter for the name. E.g.:
+	///
+	///   Meta::new().with_name("jupiter")
+	pub fn with_name(mut self, name: &str) -> Self {
+		self.name = Some(name.into());
+		self
+	}
+
+	/// Builder-style setter for the group tag.
+	pub fn with_tag(mut self, tag: &str) -> Self {
+		self.tag = Some(tag.into());
+		self
+	}
+
+	/// Builder-style setter for the radius.
+	pub fn with_radius(mut self, radius: f64) -> Self {
+		self.radius = radius;
+		self
+	}
+
+	pub fn has_name(&self, name: &str) -> bool {
+		self.name.as_deref() == Some(name)
+	}
+
+	pub fn has_tag(&self, tag: &str) -> bool {
+		self.tag.as_deref() == Some(tag)
+	}
+
+	/// Human-readable identification: the name if present,
+	/// otherwise `#index` (index of the particle in its list).
+	pub fn label(&self, index: usize) -> String {
+		match &self.name {
+			Some(name) => name.clone(),
+			None => format!("#{}", index),
+		}
+	}
+}
+
+/// Index of the (first) particle with given name.
+pub fn index_of(meta: &[Meta], name: &str) -> Option<usize> {
+	meta.iter().position(|m| m.has_name(name))
+}
+
+/// Index of the most massive particle (e.g. the sun), None if there are no particles.
+pub fn most_massive(particles: &[Particle]) -> Option<usize> {
+	(0..particles.len()).max_by(|&i, &j| particles[i].mass.total_cmp(&particles[j].mass))
+}
+
 // Add a constant velocity to each particle
 // so that the system's net momentum becomes zero.
 //
@@ -1,16 +1,37 @@
 extern crate errors;
 pub use errors::*;
 
+pub use super::animation::*;
 pub use super::brute_force::*;
+pub use super::checkpoint::*;
+pub use super::composite::*;
+pub use super::diagnostics::*;
+pub use super::draw::*;
+pub use super::elements::*;
+pub use super::exposure::*;
+pub use super::fits::*;
 pub use super::forces::*;
+pub use super::frame::*;
+pub use super::histogram::*;
 pub use super::image::*;
+pub use super::jsonl::*;
 pub use super::math::*;
+pub use super::npy::*;
 pub use super::outputs::*;
-pub use super::partial_force::*;
+pub use super::overlay::*;
 pub use super::partial_force::*;
 pub use super::particle::*;
+pub use super::projection::*;
 pub use super::render::*;
+pub use super::schedule::*;
+pub use super::selection::*;
+pub use super::snapshot::*;
+pub use super::splat::*;
 pub use super::stepper::*;
+pub use super::svg::*;
+pub use super::tonemap::*;
+pub use super::trail::*;
+pub use super::viewport::*;
 
 pub use std::f64::consts::PI;
 
@@ -0,0 +1,170 @@
+use super::prelude::*;
+
+/// Maps particles to pixels, for rendering.
+/// See `Viewport` (Cartesian) and `Polar`.
+pub trait Projection {
+	/// Continuous pixel coordinates of particle `p`, in a `w` x `h` pixel image
+	/// (pixel (i, j) covers [i, i+1) x [j, j+1)). Non-finite if `p` cannot be shown.
+	fn project(&self, p: &Particle, w: usize, h: usize) -> vec2;
+
+	/// Pixels per world unit along x and y (approximate if not uniform).
+	fn pixels_per_unit(&self, w: usize, h: usize) -> (f64, f64);
+
+	/// Does x wrap around (e.g. azimuth), so that what leaves one edge of the image enters at the other?
+	fn periodic_x(&self) -> bool {
+		false
+	}
+}
+
+impl Projection for Viewport {
+	fn project(&self, p: &Particle, w: usize, h: usize) -> vec2 {
+		self.to_pixel(p.pos, w, h)
+	}
+
+	fn pixels_per_unit(&self, w: usize, h: usize) -> (f64, f64) {
+		((w as f64) / self.width, (h as f64) / self.height)
+	}
+}
+
+/// Unwrapped polar projection around a center body:
+/// azimuth along x (-180° to 180° from `rotation`, counter-clockwise), radius along y (`min` to `max`).
+/// Circular orbits become horizontal lines, and gaps in rings and belts horizontal bands.
+///
+///   let polar = Polar::new(Radial::SemiMajorAxis, 0.2, 1.0).around(&particles[sun]);
+///
+#[derive(Clone, Debug)]
+pub struct Polar {
+	pub radial: Radial,
+	pub min: f64,
+	pub max: f64,
+	/// Logarithmic radial axis (requires min > 0).
+	pub log: bool,
+	/// Azimuth at the center of the image (radians).
+	pub rotation: f64,
+	/// Position, velocity and mass of the center body.
+	pub center: Particle,
+}
+
+/// The radial coordinate of a `Polar` projection.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Radial {
+	/// Distance to the center body.
+	Radius,
+	/// Osculating semi-major axis of the orbit around the center body.
+	/// Unbound particles are not shown.
+	SemiMajorAxis,
+}
+
+impl Polar {
+	/// Projection with a linear radial axis from `min` to `max`,
+	/// around a center body of unit mass, at rest at the origin.
+	pub fn new(radial: Radial, min: f64, max: f64) -> Self {
+		Self {
+			radial,
+			min,
+			max,
+			log: false,
+			rotation: 0.0,
+			center: Particle::new(1.0, vec2::ZERO, vec2::ZERO),
+		}
+	}
+
+	pub fn with_log(mut self, log: bool) -> Self {
+		self.log = log;
+		self
+	}
+
+	pub fn with_rotation(mut self, rotation: f64) -> Self {
+		self.rotation = rotation;
+		self
+	}
+
+	/// Centered on `body`, in its current state.
+	pub fn around(mut self, body: &Particle) -> Self {
+		self.center = Particle::new(body.mass, body.pos, body.vel);
+		self
+	}
+
+	/// Position along the radial axis, 0..1 for min..max.
+	fn radial_fraction(&self, r: f64) -> f64 {
+		if self.log {
+			f64::ln(r / self.min) / f64::ln(self.max / self.min)
+		} else {
+			(r - self.min) / (self.max - self.min)
+		}
+	}
+}
+
+impl Projection for Polar {
+	fn project(&self, p: &Particle, w: usize, h: usize) -> vec2 {
+		let d = p.pos - self.center.pos;
+		let r = match self.radial {
+			Radial::Radius => d.len(),
+			Radial::SemiMajorAxis => {
+				let el = Elements::of(p, &self.center);
+				if el.is_bound() {
+					el.a()
+				} else {
+					f64::NAN
+				}
+			}
+		};
+		let theta = f64::atan2(d.y, d.x) - self.rotation;
+		let theta = theta - 2.0 * PI * f64::floor(theta / (2.0 * PI) + 0.5); // -PI..PI
+		vec2((theta / (2.0 * PI) + 0.5) * (w as f64), self.radial_fraction(r) * (h as f64))
+	}
+
+	// At the middle of the radial range.
+	fn pixels_per_unit(&self, w: usize, h: usize) -> (f64, f64) {
+		let mid = if self.log { f64::sqrt(self.min * self.max) } else { 0.5 * (self.min + self.max) };
+		let dr = if self.log { mid * f64::ln(self.max / self.min) } else { self.max - self.min };
+		((w as f64) / (2.0 * PI * mid), (h as f64) / dr)
+	}
+
+	// Azimuth: -180° is +180°.
+	fn periodic_x(&self) -> bool {
+		true
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn polar() {
+		let sun = Particle::new(1.0, vec2(1.0, 0.0), vec2(0.0, 0.0));
+		let at = |pos, vel| Particle::new(0.0, pos, vel);
+		let (w, h) = (360, 100);
+
+		let polar = Polar::new(Radial::Radius, 0.0, 2.0).around(&sun);
+		assert!((polar.project(&at(vec2(2.0, 0.0), vec2::ZERO), w, h) - vec2(180.0, 50.0)).len() < 1e-9);
+		assert!((polar.project(&at(vec2(1.0, 0.5), vec2::ZERO), w, h) - vec2(270.0, 25.0)).len() < 1e-9);
+		assert!(polar.project(&at(vec2(0.0, -0.01), vec2::ZERO), w, h).x < 1.0);
+
+		let polar = polar.with_rotation(PI / 2.0);
+		assert!((polar.project(&at(vec2(1.0, 0.5), vec2::ZERO), w, h) - vec2(180.0, 25.0)).len() < 1e-9);
+
+		let polar = Polar::new(Radial::Radius, 0.1, 10.0).with_log(true);
+		assert!((polar.project(&at(vec2(1.0, 0.0), vec2::ZERO), w, h).y - 50.0).abs() < 1e-9);
+
+		// circular orbit of radius 0.5: a = r. Escaping: not shown.
+		let polar = Polar::new(Radial::SemiMajorAxis, 0.0, 1.0).around(&sun);
+		let circular = at(vec2(1.0, 0.5), vec2(-f64::sqrt(2.0), 0.0));
+		assert!((polar.project(&circular, w, h).y - 50.0).abs() < 1e-9);
+		assert!(!polar.project(&at(vec2(1.0, 0.5), vec2(-2.5, 0.0)), w, h).is_finite());
+	}
+
+	#[test]
+	fn render_polar() {
+		// a ring renders as a horizontal line.
+		let ring: Vec<Particle> = (0..100)
+			.map(|i| {
+				let theta = (i as f64 + 0.5) * 2.0 * PI / 100.0;
+				Particle::new(0.0, 1.1 * vec2(f64::cos(theta), f64::sin(theta)), vec2::ZERO)
+			})
+			.collect();
+		let img = render_density(&ring, 100, 10, &Polar::new(Radial::Radius, 0.0, 2.0));
+		assert_eq!(img[5].iter().sum::<f32>(), 100.0);
+	}
+}
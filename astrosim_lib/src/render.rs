@@ -1,43 +1,142 @@
 use super::image::Image;
 use super::prelude::*;
+use std::fs::File;
+use std::io::{BufWriter, Write};
 use std::path::Path;
+use std::str::FromStr;
+
+/// File format for density images.
+#[derive(Clone, Copy, Debug, PartialEq, Default)]
+pub enum DensityFormat {
+	/// 8-bit tone-mapped image (.png).
+	#[default]
+	Png,
+	/// 16-bit grayscale, tone-mapped (.png).
+	Png16,
+	/// Raw 32-bit float density, Portable Float Map (.pfm).
+	Pfm,
+	/// Raw 32-bit float density with time and world coordinates in the header (.fits).
+	Fits,
+}
 
 pub fn save_density<P: AsRef<Path>>(density: &Image<f32>, file: P) -> Result<()> {
-	let max = density.pixels().iter().fold(0.0, |a, b| f32::max(a, *b));
-	let (w, h) = density.dimensions();
-	let img = image::ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
-		let density = density[y as usize][x as usize];
-		let v = ((density / max).sqrt() * 255.0) as u8;
-		image::Rgba([v, v, v, 255])
-	});
-	img.save(file)?;
+	density_image(density).save(file)?;
 	Ok(())
 }
 
-pub fn render_density(particles: &[Particle], npix: u32, scale: f64) -> Image<f32> {
-	let mut img = Image::new(npix, npix);
+/// Save as a 16-bit grayscale PNG. Values are expected in 0..1 (e.g. from `ToneMap::normalize`)
+/// and clamped to that range.
+pub fn save_png16<P: AsRef<Path>>(img: &Image<f32>, file: P) -> Result<()> {
+	let gray: image::ImageBuffer<image::Luma<u16>, Vec<u16>> = (&img.map(|v| (f32::clamp(v, 0.0, 1.0) * 65535.0).round() as u16)).into();
+	gray.save(file)?;
+	Ok(())
+}
 
-	for p in particles {
-		let screen_pos = (p.pos / (2.0 * scale) + vec2(0.5, 0.5)) * (npix as f64) + vec2(0.5, 0.5);
-		let x = screen_pos.x as i32;
-		let y = screen_pos.y as i32;
-		let npix = npix as i32;
-		if x >= 0 && x < npix && y >= 0 && y < npix {
-			img[y as usize][x as usize] += 1.0;
-		}
+/// Save raw values as a grayscale Portable Float Map (32-bit little-endian floats).
+/// PFM stores rows bottom to top, so the first image row (y = 0) is shown at the bottom.
+pub fn save_pfm<P: AsRef<Path>>(img: &Image<f32>, file: P) -> Result<()> {
+	let file = file.as_ref();
+	let msg = format!("write {}", file.to_string_lossy());
+	let mut w = BufWriter::new(File::create(file).msg(&msg)?);
+	write!(w, "Pf\n{} {}\n-1.0\n", img.width(), img.height()).msg(&msg)?;
+	for v in img.pixels() {
+		w.write_all(&v.to_le_bytes()).msg(&msg)?;
 	}
+	w.flush().msg(&msg)?;
+	Ok(())
+}
+
+/// Grayscale image of the density, as written by `save_density`.
+/// See `ToneMap` for other stretches and colormaps.
+pub fn density_image(density: &Image<f32>) -> image::RgbaImage {
+	ToneMap::default().apply(density)
+}
+
+/// Number of particles per pixel, in a `w` x `h` pixel image
+/// of a `Viewport` (or other `Projection`).
+pub fn render_density<P: Projection + ?Sized>(particles: &[Particle], w: u32, h: u32, projection: &P) -> Image<f32> {
+	let mut img = Image::new(w, h);
+	accumulate_density(&mut img, particles, projection, 1.0);
 	img
 }
 
-pub fn accumulate_density(img: &mut Image<f32>, particles: &[Particle], scale: f64, weight: f32) {
-	let npix = img.width();
-	for p in particles {
-		let screen_pos = (p.pos / (2.0 * scale) + vec2(0.5, 0.5)) * (npix as f64) + vec2(0.5, 0.5);
-		let x = screen_pos.x as i32;
-		let y = screen_pos.y as i32;
-		let npix = npix as i32;
-		if x >= 0 && x < npix && y >= 0 && y < npix {
-			img[y as usize][x as usize] += weight; // TODO: mass? What about 0 mass particles?
+/// Add `weight` to the pixel under each particle.
+/// Particles can be any selection, e.g. `Selection::tag(sim.meta(), "asteroid").select(particles)`.
+/// See `splat_density` for anti-aliased deposits.
+pub fn accumulate_density<'a, I, P>(img: &mut Image<f32>, particles: I, projection: &P, weight: f32)
+where
+	I: IntoIterator<Item = &'a Particle>,
+	P: Projection + ?Sized,
+{
+	// TODO: mass? What about 0 mass particles?
+	splat_density(img, particles, projection, Kernel::Nearest, weight)
+}
+
+impl DensityFormat {
+	/// File name extension.
+	pub fn extension(self) -> &'static str {
+		match self {
+			DensityFormat::Png | DensityFormat::Png16 => "png",
+			DensityFormat::Pfm => "pfm",
+			DensityFormat::Fits => "fits",
+		}
+	}
+}
+
+impl FromStr for DensityFormat {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"png" => Ok(DensityFormat::Png),
+			"png16" => Ok(DensityFormat::Png16),
+			"pfm" => Ok(DensityFormat::Pfm),
+			"fits" => Ok(DensityFormat::Fits),
+			_ => err(format!("unknown image format {:?}, want png, png16, pfm or fits", s)),
 		}
 	}
 }
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn non_square() {
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(1.0, vec2(1.9, 0.0), vec2(0.0, 0.0)),
+			Particle::new(1.0, vec2(0.0, 1.9), vec2(0.0, 0.0)), // outside
+		];
+		let img = render_density(&particles, 40, 20, &Viewport::from_scale(2.0).fit(40, 20));
+		assert_eq!(img[10][20], 1.0);
+		assert_eq!(img[10][39], 1.0);
+		assert_eq!(img.pixels().iter().sum::<f32>(), 2.0);
+	}
+
+	#[test]
+	fn formats() {
+		let img = Image::<f32>::from_fn(3, 2, |x, y| 0.25 * (x + y) as f32);
+		let dir = std::env::temp_dir();
+		let (pfm, png) = (
+			dir.join(format!("astrosim_pfm_test_{}.pfm", std::process::id())),
+			dir.join(format!("astrosim_png16_test_{}.png", std::process::id())),
+		);
+
+		save_pfm(&img, &pfm).unwrap();
+		let bytes = std::fs::read(&pfm).unwrap();
+		let header = b"Pf\n3 2\n-1.0\n";
+		assert_eq!(&bytes[..header.len()], header);
+		assert_eq!(bytes.len(), header.len() + 6 * 4);
+		assert_eq!(bytes[header.len() + 5 * 4..], 0.75f32.to_le_bytes());
+
+		save_png16(&img, &png).unwrap();
+		let read = image::open(&png).unwrap().into_luma16();
+		assert_eq!(read.get_pixel(2, 1)[0], 49151);
+		assert_eq!(read.get_pixel(0, 0)[0], 0);
+
+		std::fs::remove_file(&pfm).unwrap();
+		std::fs::remove_file(&png).unwrap();
+		assert_eq!("fits".parse::<DensityFormat>().unwrap().extension(), "fits");
+	}
+}
@@ -0,0 +1,154 @@
+use super::prelude::*;
+
+/// When to write an output: every so many time steps,
+/// or at given simulated times.
+///
+/// Time-based outputs are written at exactly the scheduled times:
+/// `Stepper::advance_with_output` truncates time steps to land on them.
+/// So the number of snapshots is known in advance, regardless of the adaptive time step.
+#[derive(Clone, Debug, PartialEq)]
+pub enum Schedule {
+	/// Every n-th time step (including the initial state).
+	Steps(u64),
+
+	/// At t = 0, interval, 2*interval, ...
+	Interval(f64),
+
+	/// At the given times, in increasing order.
+	Times(Vec<f64>),
+}
+
+impl Schedule {
+	/// After every time step.
+	pub fn every_step() -> Self {
+		Schedule::Steps(1)
+	}
+
+	/// At t = 0, interval, 2*interval, ... The interval must be positive.
+	pub fn interval(interval: f64) -> Result<Self> {
+		if interval.is_nan() || interval <= 0.0 {
+			return err(format!("schedule: interval must be > 0, got {}", interval));
+		}
+		Ok(Schedule::Interval(interval))
+	}
+
+	/// `n` outputs evenly spread over (0, end_time], plus one for the initial state.
+	pub fn evenly(end_time: f64, n: u32) -> Result<Self> {
+		Self::interval(end_time / (n as f64))
+	}
+
+	/// `n` logarithmically spaced times from `start` to `end` (inclusive).
+	pub fn logarithmic(start: f64, end: f64, n: u32) -> Self {
+		if n < 2 {
+			return Schedule::Times(vec![end]);
+		}
+		let ratio = end / start;
+		let mut times: Vec<f64> = (0..n).map(|i| start * f64::powf(ratio, (i as f64) / ((n - 1) as f64))).collect();
+		// exact end points, despite round-off.
+		times[0] = start;
+		times[(n - 1) as usize] = end;
+		Schedule::Times(times)
+	}
+
+	/// Explicit times, sorted.
+	pub fn times(mut times: Vec<f64>) -> Result<Self> {
+		if times.iter().any(|t| t.is_nan()) {
+			return err("schedule: times must be numbers, got NaN");
+		}
+		times.sort_by(f64::total_cmp);
+		Ok(Schedule::Times(times))
+	}
+
+	/// First scheduled time at or after `t`
+	/// (or strictly after, if `strict`). Infinite if there is none.
+	/// Step-based schedules have no scheduled times.
+	pub fn next_time(&self, t: f64, strict: bool) -> f64 {
+		let after = |s: f64| if strict { s > t } else { s >= t };
+		match self {
+			Schedule::Steps(_) => INF,
+			Schedule::Interval(interval) => {
+				assert!(*interval > 0.0, "Schedule::Interval({}): must be > 0", interval);
+				// Estimate the index k of the next time (k * interval),
+				// then correct for round-off.
+				let mut k = f64::max(0.0, f64::floor(t / interval));
+				while k > 0.0 && after((k - 1.0) * interval) {
+					k -= 1.0;
+				}
+				while !after(k * interval) {
+					k += 1.0;
+				}
+				k * interval
+			}
+			Schedule::Times(times) => times.iter().copied().find(|&s| after(s)).unwrap_or(INF),
+		}
+	}
+
+	/// Is step number `step_count` scheduled? (Step-based schedules only).
+	pub fn is_step_due(&self, step_count: u64) -> bool {
+		match self {
+			Schedule::Steps(n) => *n != 0 && step_count % n == 0,
+			_ => false,
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn interval() {
+		let s = Schedule::Interval(0.1);
+		assert_eq!(s.next_time(0.0, false), 0.0);
+		assert_eq!(s.next_time(0.0, true), 0.1);
+		assert_eq!(s.next_time(0.05, true), 0.1);
+
+		// 0.3/0.1 == 2.9999999999999996: must not schedule 0.3 twice.
+		let t = s.next_time(0.25, false);
+		assert!(s.next_time(t, true) > t + 0.05);
+	}
+
+	#[test]
+	fn times() {
+		let s = Schedule::times(vec![3.0, 1.0, 2.0]).unwrap();
+		assert_eq!(s.next_time(0.0, false), 1.0);
+		assert_eq!(s.next_time(1.0, false), 1.0);
+		assert_eq!(s.next_time(1.0, true), 2.0);
+		assert_eq!(s.next_time(3.0, true), INF);
+
+		assert!(Schedule::times(vec![1.0, f64::NAN]).is_err());
+		assert!(Schedule::interval(-1.0).is_err());
+	}
+
+	#[test]
+	fn logarithmic() {
+		assert_eq!(Schedule::logarithmic(0.01, 100.0, 5), Schedule::Times(vec![0.01, 0.1, 1.0, 10.0, 100.0]));
+	}
+
+	// Scheduled outputs land on exactly the scheduled times,
+	// whatever the adaptive time step does.
+	#[test]
+	fn predictable_count() {
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(0.0, vec2(1.0, 0.0), vec2(0.0, 0.3)), // eccentric orbit
+		];
+		let mut sim = Stepper::new(particles, BruteForce::new());
+
+		struct Times(std::rc::Rc<std::cell::RefCell<Vec<f64>>>);
+		impl<F: Forces> OutputSink<F> for Times {
+			fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+				self.0.borrow_mut().push(sim.time());
+				Ok(())
+			}
+		}
+		let times = std::rc::Rc::new(std::cell::RefCell::new(Vec::new()));
+		let dir = std::env::temp_dir().join(format!("astrosim_schedule_test_{}", std::process::id()));
+		let mut outputs = Outputs::new(&dir).unwrap().with_sink(Schedule::Interval(0.25), Box::new(Times(times.clone())));
+		sim.advance_with_output(2.0, &mut outputs).unwrap();
+		std::fs::remove_dir(&dir).unwrap();
+
+		let want: Vec<f64> = (0..=8).map(|i| 0.25 * (i as f64)).collect();
+		assert_eq!(*times.borrow(), want);
+	}
+}
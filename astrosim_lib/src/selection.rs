@@ -0,0 +1,62 @@
+use super::prelude::*;
+use std::ops::Range;
+use std::sync::Arc;
+
+/// Which particles belong to a group: a composite layer, trails, a histogram, ...
+///
+/// Names and tags are looked up once, when the selection is made (e.g. `Selection::tag`),
+/// so that membership is a cheap test on every step.
+#[derive(Clone)]
+pub enum Selection {
+	All,
+	/// Particles by index.
+	Range(Range<usize>),
+	/// Particles whose index is set, e.g. looked up by name or tag.
+	Mask(Arc<[bool]>),
+	Predicate(Arc<dyn Fn(&Particle) -> bool + Send + Sync>),
+}
+
+impl Selection {
+	pub fn range(range: Range<usize>) -> Self {
+		Selection::Range(range)
+	}
+
+	/// Particles tagged `tag`, according to `meta` (indexed like the particles).
+	pub fn tag(meta: &[Meta], tag: &str) -> Self {
+		Self::matching(meta, |m| m.has_tag(tag))
+	}
+
+	/// The body named `name`.
+	pub fn name(meta: &[Meta], name: &str) -> Self {
+		Self::matching(meta, |m| m.has_name(name))
+	}
+
+	/// The bodies with any of these names.
+	pub fn names<S: AsRef<str>>(meta: &[Meta], names: &[S]) -> Self {
+		Self::matching(meta, |m| names.iter().any(|name| m.has_name(name.as_ref())))
+	}
+
+	/// Particles whose metadata matches.
+	pub fn matching<M: Fn(&Meta) -> bool>(meta: &[Meta], matches: M) -> Self {
+		Selection::Mask(meta.iter().map(matches).collect())
+	}
+
+	pub fn predicate<P: Fn(&Particle) -> bool + Send + Sync + 'static>(p: P) -> Self {
+		Selection::Predicate(Arc::new(p))
+	}
+
+	/// Does particle `p`, with index `i`, belong to the selection?
+	pub fn contains(&self, i: usize, p: &Particle) -> bool {
+		match self {
+			Selection::All => true,
+			Selection::Range(r) => r.contains(&i),
+			Selection::Mask(mask) => mask.get(i).copied().unwrap_or(false),
+			Selection::Predicate(f) => f(p),
+		}
+	}
+
+	/// The selected particles.
+	pub fn select<'a>(&'a self, particles: &'a [Particle]) -> impl Iterator<Item = &'a Particle> + 'a {
+		particles.iter().enumerate().filter(move |(i, p)| self.contains(*i, p)).map(|(_, p)| p)
+	}
+}
@@ -0,0 +1,375 @@
+use super::binio::*;
+use super::prelude::*;
+use std::fs::{File, OpenOptions};
+use std::io::{BufReader, BufWriter, Seek, SeekFrom, Write};
+use std::path::Path;
+
+// File layout (little-endian, see binio):
+//
+//   magic "ASTROSNP", u32 version, units (3 strings: length, mass, time)
+//   snapshots, each:
+//     u64 length of the rest of the record (so that readers can skip it)
+//     f64 time, u64 step count, u64 particle count, u32 column count
+//     columns, each: name (string), type (u8), particle count values
+//
+// Readers skip columns with names they do not know, so columns of the existing types
+// can be added without a version bump (a new column type needs one).
+const MAGIC: &[u8] = b"ASTROSNP";
+const VERSION: u32 = 1;
+
+const COL_F64: u8 = 0;
+const COL_OPT_STR: u8 = 1;
+
+// Each particle takes at least this many bytes in a record:
+// mass, position, velocity, radius, name and tag markers.
+const MIN_PARTICLE_BYTES: u64 = 8 + 16 + 16 + 8 + 1 + 1;
+
+/// Physical meaning of the simulation units, recorded in snapshot files.
+/// The simulation itself uses G = 1.
+#[derive(Clone, Debug, PartialEq)]
+pub struct Units {
+	pub length: String,
+	pub mass: String,
+	pub time: String,
+}
+
+impl Default for Units {
+	/// Dimensionless code units.
+	fn default() -> Self {
+		Self {
+			length: "1".into(),
+			mass: "1".into(),
+			time: "1".into(),
+		}
+	}
+}
+
+/// The particles at one point in time.
+#[derive(Clone, Debug)]
+pub struct Snapshot {
+	pub time: f64,
+	pub step_count: u64,
+	pub particles: Vec<Particle>,
+	/// Names, tags and radii, indexed like the particles.
+	pub meta: Vec<Meta>,
+}
+
+impl Snapshot {
+	/// Snapshot of the current simulation state.
+	pub fn of<F: Forces>(sim: &Stepper<F>) -> Self {
+		Self {
+			time: sim.time(),
+			step_count: sim.step_count(),
+			particles: sim.particles().into(),
+			meta: sim.meta().into(),
+		}
+	}
+}
+
+/// Writes snapshots to a multi-snapshot file, to be read by `SnapshotReader`.
+pub struct SnapshotWriter {
+	out: BufWriter<File>,
+}
+
+impl SnapshotWriter {
+	/// Create a new file, without snapshots yet.
+	pub fn create(file: &Path, units: &Units) -> Result<Self> {
+		let msg = format!("create {}", file.to_string_lossy());
+		let mut out = BufWriter::new(File::create(file).msg(&msg)?);
+		out.write_all(MAGIC)?;
+		out.write_u32(VERSION)?;
+		out.write_str(&units.length)?;
+		out.write_str(&units.mass)?;
+		out.write_str(&units.time)?;
+		out.flush()?;
+		Ok(Self { out })
+	}
+
+	/// Open an existing file to append more snapshots.
+	/// Snapshots after `time` (e.g. written after the checkpoint being resumed from)
+	/// and a partially written snapshot at the end (e.g. after a crash) are discarded.
+	pub fn append(file: &Path, time: f64) -> Result<Self> {
+		let mut r = SnapshotReader::open(file)?;
+		let mut end = r.end;
+		for i in 0..r.len() {
+			if r.time(i)? > time {
+				end = r.offsets[i];
+				break;
+			}
+		}
+		let msg = format!("append {}", file.to_string_lossy());
+		let f = OpenOptions::new().write(true).open(file).msg(&msg)?;
+		f.set_len(end).msg(&msg)?;
+		let mut out = BufWriter::new(f);
+		out.seek(SeekFrom::End(0))?;
+		Ok(Self { out })
+	}
+
+	pub fn write(&mut self, snap: &Snapshot) -> Result<()> {
+		let (ps, meta) = (&snap.particles, &snap.meta);
+		let mut rec = Vec::new();
+		rec.write_f64(snap.time)?;
+		rec.write_u64(snap.step_count)?;
+		rec.write_u64(ps.len() as u64)?;
+
+		rec.write_u32(8)?; // columns
+		f64_column(&mut rec, "mass", ps.iter().map(|p| p.mass))?;
+		f64_column(&mut rec, "x", ps.iter().map(|p| p.pos.x))?;
+		f64_column(&mut rec, "y", ps.iter().map(|p| p.pos.y))?;
+		f64_column(&mut rec, "vx", ps.iter().map(|p| p.vel.x))?;
+		f64_column(&mut rec, "vy", ps.iter().map(|p| p.vel.y))?;
+		f64_column(&mut rec, "radius", meta.iter().map(|m| m.radius))?;
+		str_column(&mut rec, "name", meta.iter().map(|m| m.name.as_deref()))?;
+		str_column(&mut rec, "tag", meta.iter().map(|m| m.tag.as_deref()))?;
+
+		self.out.write_u64(rec.len() as u64)?;
+		self.out.write_all(&rec)?;
+		self.out.flush()?;
+		Ok(())
+	}
+}
+
+fn f64_column<I: Iterator<Item = f64>>(w: &mut Vec<u8>, name: &str, values: I) -> Result<()> {
+	w.write_str(name)?;
+	w.write_u8(COL_F64)?;
+	for v in values {
+		w.write_f64(v)?;
+	}
+	Ok(())
+}
+
+fn str_column<'a, I: Iterator<Item = Option<&'a str>>>(w: &mut Vec<u8>, name: &str, values: I) -> Result<()> {
+	w.write_str(name)?;
+	w.write_u8(COL_OPT_STR)?;
+	for v in values {
+		w.write_opt_str(v)?;
+	}
+	Ok(())
+}
+
+/// Random access to the snapshots in a file written by `SnapshotWriter`.
+pub struct SnapshotReader {
+	r: BufReader<File>,
+	units: Units,
+	offsets: Vec<u64>, // start of each snapshot record.
+	end: u64,          // end of the last complete record.
+}
+
+impl SnapshotReader {
+	pub fn open(file: &Path) -> Result<Self> {
+		let msg = format!("read {}", file.to_string_lossy());
+		Self::open_(file).map_err(|e| error(format!("{}: {}", &msg, e)))
+	}
+
+	fn open_(file: &Path) -> Result<Self> {
+		let mut r = BufReader::new(File::open(file)?);
+		r.read_magic(MAGIC)?;
+		let version = r.read_u32()?;
+		if version != VERSION {
+			return err(format!("unsupported snapshot version {} (want {})", version, VERSION));
+		}
+		let units = Units {
+			length: r.read_str()?,
+			mass: r.read_str()?,
+			time: r.read_str()?,
+		};
+
+		// Index the records by skipping over them.
+		let file_len = r.get_ref().metadata()?.len();
+		let mut offsets = Vec::new();
+		let mut pos = r.stream_position()?;
+		while pos + 8 <= file_len {
+			let len = r.read_u64()?;
+			match (pos + 8).checked_add(len) {
+				Some(end) if end <= file_len => (),
+				_ => break, // incomplete last record.
+			}
+			offsets.push(pos);
+			pos = r.seek(SeekFrom::Current(len as i64))?;
+		}
+		Ok(Self { r, units, offsets, end: pos })
+	}
+
+	pub fn units(&self) -> &Units {
+		&self.units
+	}
+
+	/// Number of snapshots.
+	pub fn len(&self) -> usize {
+		self.offsets.len()
+	}
+
+	pub fn is_empty(&self) -> bool {
+		self.offsets.is_empty()
+	}
+
+	/// Time of snapshot `i`, without reading its particles.
+	pub fn time(&mut self, i: usize) -> Result<f64> {
+		self.seek(i)?;
+		self.r.read_f64()
+	}
+
+	/// Read snapshot `i` (0-based).
+	pub fn read(&mut self, i: usize) -> Result<Snapshot> {
+		self.seek(i)?;
+		let record_len = self.offsets.get(i + 1).copied().unwrap_or(self.end) - self.offsets[i] - 8;
+		let r = &mut self.r;
+		let time = r.read_f64()?;
+		let step_count = r.read_u64()?;
+		let n = r.read_u64()?;
+		if n > record_len / MIN_PARTICLE_BYTES {
+			return err(format!("snapshot {}: truncated or corrupt: {} particles do not fit in {} bytes", i, n, record_len));
+		}
+		let n = n as usize;
+		let mut particles = vec![Particle::new(0.0, vec2(0.0, 0.0), vec2(0.0, 0.0)); n];
+		let mut meta = vec![Meta::default(); n];
+
+		let ncol = r.read_u32()?;
+		for _c in 0..ncol {
+			let name = r.read_str()?;
+			match r.read_u8()? {
+				COL_F64 => {
+					for (p, m) in particles.iter_mut().zip(&mut meta) {
+						let v = r.read_f64()?;
+						match name.as_str() {
+							"mass" => p.mass = v,
+							"x" => p.pos.x = v,
+							"y" => p.pos.y = v,
+							"vx" => p.vel.x = v,
+							"vy" => p.vel.y = v,
+							"radius" => m.radius = v,
+							_ => (), // unknown column
+						}
+					}
+				}
+				COL_OPT_STR => {
+					for m in &mut meta {
+						let v = r.read_opt_str()?;
+						match name.as_str() {
+							"name" => m.name = v,
+							"tag" => m.tag = v,
+							_ => (),
+						}
+					}
+				}
+				t => return err(format!("snapshot {}: column {}: unknown type {}", i, name, t)),
+			}
+		}
+		Ok(Snapshot { time, step_count, particles, meta })
+	}
+
+	// Position the reader at the start of snapshot i's contents.
+	fn seek(&mut self, i: usize) -> Result<()> {
+		match self.offsets.get(i) {
+			None => err(format!("snapshot {} out of range (have {})", i, self.len())),
+			Some(&offset) => {
+				self.r.seek(SeekFrom::Start(offset + 8))?;
+				Ok(())
+			}
+		}
+	}
+}
+
+/// Output sink writing snapshots to a single multi-snapshot file.
+pub struct SnapshotFile {
+	w: SnapshotWriter,
+}
+
+impl SnapshotFile {
+	pub const FILE: &'static str = "snapshots.bin";
+
+	/// Write to `file`, or append to existing snapshots
+	/// when resuming from `resume` time (see `SnapshotWriter::append`).
+	pub fn new(file: &Path, resume: Option<f64>, units: &Units) -> Result<Self> {
+		let w = match resume {
+			Some(time) if file.exists() => SnapshotWriter::append(file, time)?,
+			_ => SnapshotWriter::create(file, units)?,
+		};
+		Ok(Self { w })
+	}
+}
+
+impl<F: Forces> OutputSink<F> for SnapshotFile {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		self.w.write(&Snapshot::of(sim))
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn random_access() {
+		let file = std::env::temp_dir().join(format!("astrosim_snapshot_test_{}.bin", std::process::id()));
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), //
+			Particle::new(0.0, vec2(1.0, 0.0), vec2(0.0, 1.0)),
+		];
+		let meta = vec![Meta::new().with_name("sun"), Meta::new().with_tag("asteroid").with_radius(1e-5)];
+		let mut sim = Stepper::new(particles, BruteForce::new()).with_meta(meta);
+		let units = Units {
+			length: "AU".into(),
+			..Units::default()
+		};
+
+		let mut w = SnapshotWriter::create(&file, &units).unwrap();
+		w.write(&Snapshot::of(&sim)).unwrap();
+		sim.advance(1.0);
+		w.write(&Snapshot::of(&sim)).unwrap();
+		drop(w);
+		let mut w = SnapshotWriter::append(&file, f64::INFINITY).unwrap();
+		sim.advance(1.0);
+		w.write(&Snapshot::of(&sim)).unwrap();
+		drop(w);
+
+		// a truncated record, as after a crash, is ignored.
+		let len = std::fs::metadata(&file).unwrap().len();
+		OpenOptions::new().append(true).open(&file).unwrap().write_all(&[1, 2, 3]).unwrap();
+
+		let mut r = SnapshotReader::open(&file).unwrap();
+		assert_eq!(r.units(), &units);
+		assert_eq!(r.len(), 3);
+		assert_eq!(r.end, len);
+		assert_eq!(r.time(1).unwrap(), 1.0);
+
+		let snap = r.read(2).unwrap();
+		assert_eq!(snap.time, 2.0);
+		assert_eq!(snap.step_count, sim.step_count());
+		for (a, b) in snap.particles.iter().zip(sim.particles()) {
+			assert_eq!(a.mass, b.mass);
+			assert_eq!(a.pos, b.pos);
+			assert_eq!(a.vel, b.vel);
+		}
+		assert_eq!(snap.meta, sim.meta());
+		assert_eq!(r.read(0).unwrap().particles[1].pos, vec2(1.0, 0.0));
+		assert!(r.read(3).is_err());
+
+		// resuming from t=1 discards the later snapshot.
+		drop(SnapshotWriter::append(&file, 1.0).unwrap());
+		assert_eq!(SnapshotReader::open(&file).unwrap().len(), 2);
+
+		std::fs::remove_file(&file).unwrap();
+	}
+	#[test]
+	fn corrupt_particle_count() {
+		let file = std::env::temp_dir().join(format!("astrosim_snapshot_test_corrupt_{}.bin", std::process::id()));
+		let sim = Stepper::new(vec![Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0))], BruteForce::new());
+		let mut w = SnapshotWriter::create(&file, &Units::default()).unwrap();
+		w.write(&Snapshot::of(&sim)).unwrap();
+		drop(w);
+
+		// particle count after the record length, time and step count.
+		let count_at = SnapshotReader::open(&file).unwrap().offsets[0] + 24;
+		for &n in &[1000u64, 1 << 40, u64::MAX] {
+			let mut f = OpenOptions::new().write(true).open(&file).unwrap();
+			f.seek(SeekFrom::Start(count_at)).unwrap();
+			f.write_all(&n.to_le_bytes()).unwrap();
+			drop(f);
+			let result = SnapshotReader::open(&file).unwrap().read(0);
+			assert!(result.err().unwrap().to_string().contains("do not fit"), "n = {}", n);
+		}
+
+		std::fs::remove_file(&file).unwrap();
+	}
+}
@@ -0,0 +1,341 @@
+use super::prelude::*;
+use std::str::FromStr;
+use std::thread;
+
+/// How a particle's weight is deposited onto pixels.
+///
+/// All kernels except `Nearest` are anti-aliased: a particle moving smoothly across
+/// pixels changes the image smoothly. Weights are normalized so that each particle
+/// deposits exactly its weight (minus what falls outside the image).
+#[derive(Clone, Copy, Debug, Default, PartialEq)]
+pub enum Kernel {
+	/// All weight to the pixel under the particle.
+	#[default]
+	Nearest,
+	/// Cloud-in-cell: bilinear over the 2x2 nearest pixels.
+	Cic,
+	/// Triangular-shaped cloud: quadratic over the 3x3 nearest pixels.
+	Tsc,
+	/// Gaussian with this standard deviation, truncated at 3 standard deviations.
+	Gaussian(Radius),
+}
+
+/// A length in world units or in pixels.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Radius {
+	World(f64),
+	Pixels(f64),
+}
+
+/// Density accumulated on several threads, like `splat_density`.
+///
+/// Each chunk of particles is accumulated into its own image (tile), kept across steps,
+/// and the tiles are only added up when the density is needed (`add_to`).
+/// The number of chunks depends only on the number of particles, not on the threads,
+/// so that the result is the same on any machine (but may differ from `splat_density` by rounding).
+///
+///   let mut splat = ParallelSplat::new(0);
+///   // every step:
+///   splat.splat(&mut img, particles, &Selection::All, &viewport, Kernel::Cic, weight);
+///   // when saving:
+///   splat.add_to(&mut img);
+///   splat.clear();
+///
+#[derive(Clone, Debug, Default)]
+pub struct ParallelSplat {
+	threads: usize,
+	tiles: Vec<Image<f32>>,
+}
+
+impl ParallelSplat {
+	// Not worth a tile for fewer particles than this.
+	const MIN_CHUNK: usize = 4096;
+	// Nor more tiles than this (the most threads used).
+	const MAX_TILES: usize = 16;
+
+	/// Split over `threads` threads (0: all available cores).
+	pub fn new(threads: usize) -> Self {
+		Self { threads, tiles: Vec::new() }
+	}
+
+	/// Add `weight` around each selected particle, like `splat_density`.
+	/// Few particles go straight to `img`, others to tiles of the same size, see `add_to`.
+	pub fn splat<P>(&mut self, img: &mut Image<f32>, particles: &[Particle], selection: &Selection, projection: &P, kernel: Kernel, weight: f32)
+	where
+		P: Projection + Sync + ?Sized,
+	{
+		let tiles = usize::min(particles.len().div_ceil(Self::MIN_CHUNK), Self::MAX_TILES);
+		if tiles <= 1 {
+			return splat_density(img, selection.select(particles), projection, kernel, weight);
+		}
+		let (w, h) = (img.width() as u32, img.height() as u32);
+		while self.tiles.len() < tiles {
+			self.tiles.push(Image::new(w, h));
+		}
+
+		// Chunks of particles, with the index of their first particle.
+		let chunk_len = particles.len().div_ceil(tiles);
+		let chunks: Vec<(usize, &[Particle])> = particles.chunks(chunk_len).enumerate().map(|(i, c)| (i * chunk_len, c)).collect();
+		let splat_all = |tiles: &mut [Image<f32>], chunks: &[(usize, &[Particle])]| {
+			for (tile, &(start, chunk)) in tiles.iter_mut().zip(chunks) {
+				let selected = chunk.iter().enumerate().filter(|&(i, p)| selection.contains(start + i, p)).map(|(_, p)| p);
+				splat_density(tile, selected, projection, kernel, weight);
+			}
+		};
+		let threads = if self.threads == 0 { available_threads() } else { self.threads };
+		let per_thread = chunks.len().div_ceil(threads);
+		if per_thread >= chunks.len() {
+			return splat_all(&mut self.tiles, &chunks);
+		}
+		thread::scope(|s| {
+			for (tiles, chunks) in self.tiles.chunks_mut(per_thread).zip(chunks.chunks(per_thread)) {
+				s.spawn(move || splat_all(tiles, chunks));
+			}
+		});
+	}
+
+	/// Add the tiles to `img`, in order.
+	pub fn add_to(&self, img: &mut Image<f32>) {
+		for tile in &self.tiles {
+			img.add(tile);
+		}
+	}
+
+	/// Reset the tiles to zero, e.g. after `add_to`.
+	pub fn clear(&mut self) {
+		for tile in &mut self.tiles {
+			tile.clear();
+		}
+	}
+}
+
+/// Number of threads that can run in parallel (e.g. CPU cores), at least 1.
+pub fn available_threads() -> usize {
+	thread::available_parallelism().map(|n| n.get()).unwrap_or(1)
+}
+
+/// Add `weight` to the image around each particle, distributed by `kernel`.
+/// Particles can be any selection, e.g. `Selection::tag(sim.meta(), "asteroid").select(particles)`.
+/// `projection` is typically a `Viewport`. With a periodic projection (`Polar`), weight wraps around in x.
+pub fn splat_density<'a, I, P>(img: &mut Image<f32>, particles: I, projection: &P, kernel: Kernel, weight: f32)
+where
+	I: IntoIterator<Item = &'a Particle>,
+	P: Projection + ?Sized,
+{
+	let (w, h) = (img.width(), img.height());
+	// Gaussian standard deviation in pixels, along x and y.
+	let sigma = match kernel {
+		Kernel::Gaussian(Radius::World(r)) => {
+			let (sx, sy) = projection.pixels_per_unit(w, h);
+			(r * sx, r * sy)
+		}
+		Kernel::Gaussian(Radius::Pixels(r)) => (r, r),
+		_ => (0.0, 0.0),
+	};
+
+	let periodic = projection.periodic_x();
+	let mut wx = Vec::new();
+	let mut wy = Vec::new();
+	for p in particles {
+		let pix = projection.project(p, w, h);
+		if !pix.is_finite() {
+			continue;
+		}
+		let x0 = weights(kernel, pix.x, sigma.0, &mut wx);
+		let y0 = weights(kernel, pix.y, sigma.1, &mut wy);
+		for (j, &wy) in wy.iter().enumerate() {
+			let y = y0 + j as i64;
+			if y < 0 || y >= h as i64 {
+				continue;
+			}
+			let row = &mut img[y as usize];
+			for (i, &wx) in wx.iter().enumerate() {
+				let x = if periodic { (x0 + i as i64).rem_euclid(w as i64) } else { x0 + i as i64 };
+				if x >= 0 && x < w as i64 {
+					row[x as usize] += weight * (wx * wy) as f32;
+				}
+			}
+		}
+	}
+}
+
+// Weights along one axis, for continuous pixel coordinate `x` (pixel i covers [i, i+1)).
+// Stores the weights (summing to 1) in `w` and returns the index of the first pixel.
+fn weights(kernel: Kernel, x: f64, sigma: f64, w: &mut Vec<f64>) -> i64 {
+	w.clear();
+	match kernel {
+		Kernel::Nearest => {
+			w.push(1.0);
+			x.floor() as i64
+		}
+		Kernel::Cic => {
+			let c = x - 0.5; // relative to pixel centers
+			let i = c.floor();
+			let t = c - i;
+			w.extend_from_slice(&[1.0 - t, t]);
+			i as i64
+		}
+		Kernel::Tsc => {
+			let i = x.floor();
+			let d = x - i - 0.5; // offset from the nearest pixel center, -0.5..0.5
+			w.extend_from_slice(&[0.5 * (0.5 - d) * (0.5 - d), 0.75 - d * d, 0.5 * (0.5 + d) * (0.5 + d)]);
+			i as i64 - 1
+		}
+		Kernel::Gaussian(_) => {
+			// pixels with centers within 3 sigma.
+			let first = (x - 0.5 - 3.0 * sigma).ceil();
+			let last = (x - 0.5 + 3.0 * sigma).floor();
+			if last < first || sigma <= 0.0 {
+				// (almost) zero radius: nearest pixel.
+				return weights(Kernel::Nearest, x, sigma, w);
+			}
+			let mut sum = 0.0;
+			for i in 0..=((last - first) as i64) {
+				let d = (first + i as f64 + 0.5 - x) / sigma;
+				let v = f64::exp(-0.5 * d * d);
+				sum += v;
+				w.push(v);
+			}
+			for v in w.iter_mut() {
+				*v /= sum;
+			}
+			first as i64
+		}
+	}
+}
+
+impl FromStr for Kernel {
+	type Err = Error;
+
+	/// "nearest", "cic", "tsc", "gaussian:R" (R in world units) or "gaussian:Rpx" (R in pixels).
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"nearest" => Ok(Kernel::Nearest),
+			"cic" => Ok(Kernel::Cic),
+			"tsc" => Ok(Kernel::Tsc),
+			_ => match s.strip_prefix("gaussian:") {
+				Some(r) => {
+					let (r, pixels) = match r.strip_suffix("px") {
+						Some(r) => (r, true),
+						None => (r, false),
+					};
+					let r: f64 = r.parse().map_err(|e| error(format!("gaussian radius {:?}: {}", r, e)))?;
+					if !(r >= 0.0 && r.is_finite()) {
+						return err(format!("gaussian radius must be >= 0, got {}", r));
+					}
+					Ok(Kernel::Gaussian(if pixels { Radius::Pixels(r) } else { Radius::World(r) }))
+				}
+				None => err(format!("unknown kernel {:?}, want nearest, cic, tsc, gaussian:R or gaussian:Rpx", s)),
+			},
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn conserves_weight() {
+		let viewport = Viewport::new(10.0, 5.0);
+		let kernels = [
+			Kernel::Nearest,
+			Kernel::Cic,
+			Kernel::Tsc,
+			Kernel::Gaussian(Radius::Pixels(1.3)),
+			Kernel::Gaussian(Radius::World(0.1)),
+			Kernel::Gaussian(Radius::Pixels(0.0)),
+		];
+		for &kernel in &kernels {
+			for &pos in &[vec2(0.0, 0.0), vec2(0.123, -0.456), vec2(-1.01, 2.0), vec2(3.3, 1.77)] {
+				let mut img = Image::new(100, 50);
+				let p = Particle::new(1.0, pos, vec2(0.0, 0.0));
+				splat_density(&mut img, &[p], &viewport, kernel, 2.0);
+				let sum: f64 = img.pixels().iter().map(|&v| v as f64).sum();
+				assert!((sum - 2.0).abs() < 1e-6, "{:?} at {:?}: {}", kernel, pos, sum);
+			}
+		}
+	}
+
+	#[test]
+	fn kernels() {
+		let mut w = Vec::new();
+		// on a pixel center: cic deposits in one pixel, tsc in three.
+		assert_eq!(weights(Kernel::Cic, 3.5, 0.0, &mut w), 3);
+		assert_eq!(w, vec![1.0, 0.0]);
+		assert_eq!(weights(Kernel::Tsc, 3.5, 0.0, &mut w), 2);
+		assert_eq!(w, vec![0.125, 0.75, 0.125]);
+		// between two pixel centers: equal weights.
+		assert_eq!(weights(Kernel::Cic, 4.0, 0.0, &mut w), 3);
+		assert_eq!(w, vec![0.5, 0.5]);
+		assert_eq!(weights(Kernel::Gaussian(Radius::Pixels(1.0)), 4.0, 1.0, &mut w), 1);
+		assert_eq!(w.len(), 6);
+		assert_eq!(w[2], w[3]);
+
+		// partially outside the image: the outside part is lost.
+		let mut img = Image::new(10, 10);
+		let p = Particle::new(1.0, vec2(-5.5, 0.0), vec2(0.0, 0.0));
+		splat_density(&mut img, &[p], &Viewport::new(10.0, 10.0), Kernel::Cic, 1.0);
+		assert_eq!(img[5][0], 0.5);
+		assert_eq!(img.pixels().iter().sum::<f32>(), 0.5);
+
+		// at the azimuth seam of a polar projection: wrapped around.
+		let mut img = Image::new(10, 10);
+		let p = Particle::new(1.0, vec2(-1.0, 0.0), vec2(0.0, 0.0));
+		splat_density(&mut img, &[p], &Polar::new(Radial::Radius, 0.0, 2.0), Kernel::Cic, 1.0);
+		assert_eq!((img[4][0], img[4][9], img[5][0], img[5][9]), (0.25, 0.25, 0.25, 0.25));
+
+		assert_eq!("gaussian:2px".parse::<Kernel>().unwrap(), Kernel::Gaussian(Radius::Pixels(2.0)));
+		assert_eq!("gaussian:0.01".parse::<Kernel>().unwrap(), Kernel::Gaussian(Radius::World(0.01)));
+		assert!("gaussian:-1".parse::<Kernel>().is_err());
+		assert!("box".parse::<Kernel>().is_err());
+	}
+
+	#[test]
+	fn parallel() {
+		// pseudo-random particles, several chunks.
+		let mut seed = 12345u64;
+		let mut rand = || {
+			seed = seed.wrapping_mul(6364136223846793005).wrapping_add(1442695040888963407);
+			(seed >> 11) as f64 / (1u64 << 53) as f64
+		};
+		let particles: Vec<Particle> = (0..20000).map(|_| Particle::new(0.0, vec2(rand() * 2.2 - 1.1, rand() * 2.2 - 1.1), vec2(0.0, 0.0))).collect();
+		let viewport = Viewport::from_scale(1.0);
+
+		let render = |threads, kernel, weight, selection: &Selection| {
+			let mut img = Image::new(64, 64);
+			let mut splat = ParallelSplat::new(threads);
+			// tiles kept over several steps.
+			for _ in 0..3 {
+				splat.splat(&mut img, &particles, selection, &viewport, kernel, weight);
+			}
+			splat.add_to(&mut img);
+			img
+		};
+		let all = Selection::All;
+
+		// exact with integer weights.
+		let mut serial = Image::new(64, 64);
+		for _ in 0..3 {
+			splat_density(&mut serial, &particles, &viewport, Kernel::Nearest, 1.0);
+		}
+		assert_eq!(render(3, Kernel::Nearest, 1.0, &all), serial);
+
+		// independent of the number of threads, and close to serial.
+		let mut serial = Image::new(64, 64);
+		for _ in 0..3 {
+			splat_density(&mut serial, &particles, &viewport, Kernel::Cic, 0.1);
+		}
+		let par = render(1, Kernel::Cic, 0.1, &all);
+		assert_eq!(render(4, Kernel::Cic, 0.1, &all), par);
+		assert_eq!(render(0, Kernel::Cic, 0.1, &all), par);
+		assert!(par.pixels().iter().zip(serial.pixels()).all(|(a, b)| (a - b).abs() < 1e-4));
+
+		// selections, by particle and by index across chunks.
+		let img = render(0, Kernel::Nearest, 1.0, &Selection::predicate(|p| p.pos.x > 0.0));
+		assert!(img.crop(0, 0, 32, 64).pixels().iter().all(|&v| v == 0.0));
+		let mut serial = Image::new(64, 64);
+		splat_density(&mut serial, &particles[5000..15000], &viewport, Kernel::Nearest, 3.0);
+		assert_eq!(render(2, Kernel::Nearest, 1.0, &Selection::range(5000..15000)), serial);
+	}
+}
@@ -2,18 +2,28 @@ use super::prelude::*;
 use std::mem::swap;
 
 pub struct Stepper<F: Forces> {
-	particles: Vec<Particle>,
-	force: F,
+	pub(crate) particles: Vec<Particle>,
+	pub(crate) meta: Vec<Meta>,
+	pub(crate) force: F,
 	pub target_error: f64,
 	pub min_dt: f64,
 	pub max_dt: f64,
 
 	pub dt: f64,
-	step_count: u64,
-	time: f64,
+	pub(crate) step_count: u64,
+	pub(crate) time: f64,
 
-	acc1: Vec<vec2>,
-	acc2: Vec<vec2>,
+	pub(crate) acc1: Vec<vec2>,
+	pub(crate) acc2: Vec<vec2>,
+
+	// The last step was a regular step inside advance(),
+	// dt still needs to be adjusted based on its error.
+	// Only observable by callbacks, e.g. to save a checkpoint.
+	pub(crate) adjust_pending: bool,
+	// End time of the current advance, and of the one that follows it
+	// (e.g. up to the next output time), see next_dt.
+	pub(crate) end_time: f64,
+	pub(crate) next_end_time: f64,
 }
 
 impl<F: Forces> Stepper<F> {
@@ -34,6 +44,7 @@ impl<F: Forces> Stepper<F> {
 		Self {
 			acc2: acc1.clone(),
 			acc1,
+			meta: vec![Meta::default(); particles.len()],
 			particles,
 			step_count: 0,
 			time: 0.0,
@@ -42,13 +53,28 @@ impl<F: Forces> Stepper<F> {
 			min_dt: 0.0,
 			max_dt: INF,
 			force,
+			adjust_pending: false,
+			end_time: 0.0,
+			next_end_time: 0.0,
 		}
 	}
 
+	/// Set the particles' names, tags and radii (one per particle, in the same order).
+	pub fn with_meta(mut self, meta: Vec<Meta>) -> Self {
+		assert_eq!(meta.len(), self.particles.len(), "Stepper::with_meta: need one Meta per particle");
+		self.meta = meta;
+		self
+	}
+
 	pub fn particles(&self) -> &[Particle] {
 		&self.particles
 	}
 
+	/// Names, tags and radii, indexed like the particles.
+	pub fn meta(&self) -> &[Meta] {
+		&self.meta
+	}
+
 	pub fn time(&self) -> f64 {
 		self.time
 	}
@@ -57,6 +83,16 @@ impl<F: Forces> Stepper<F> {
 		self.dt
 	}
 
+	/// Size of the next time step, while advancing (e.g. in a callback):
+	/// dt adjusted to the last step's error, truncated to land on the next output time or the end time
+	/// (0 after the last step).
+	pub fn next_dt(&self) -> f64 {
+		let dt = if self.adjust_pending { self.adjusted_dt() } else { self.dt };
+		// At an output time, advancing continues up to the next one.
+		let end = if self.time < self.end_time { self.end_time } else { self.next_end_time };
+		f64::max(0.0, f64::min(dt, end - self.time))
+	}
+
 	pub fn fix_dt(&mut self, dt: f64) {
 		self.dt = dt;
 		self.min_dt = dt;
@@ -67,37 +103,83 @@ impl<F: Forces> Stepper<F> {
 		self.step_count
 	}
 
+	pub fn force(&self) -> &F {
+		&self.force
+	}
+
+	/// Energy, momentum and angular momentum of the current state.
+	pub fn diagnostics(&self) -> Diagnostics {
+		Diagnostics::new(&self.particles, &self.force)
+	}
+
 	/// Advance time by exactly total_time, without writing any output.
 	pub fn advance(&mut self, total_time: f64) {
 		// advance with no-op, no-error output function.
 		self.advance_with_callback(total_time, |_| Ok(())).unwrap()
 	}
 
-	/// Advance time by exactly
-	// pub fn advance_with_output(&mut self, total_time: f64, outputs: &mut Outputs) -> Result<()> {
-	// 	self.advance_with_callback(total_time, |s| outputs.output(s))
-	// }
+	/// Advance time by exactly total_time, passing each step to the outputs.
+	pub fn advance_with_output(&mut self, total_time: f64, outputs: &mut Outputs<F>) -> Result<()> {
+		self.advance_until_with_output(self.time + total_time, outputs)
+	}
+
+	/// Advance time up to exactly end_time, passing each step to the outputs.
+	/// Time steps are truncated to land exactly on the outputs' scheduled times.
+	pub fn advance_until_with_output(&mut self, end_time: f64, outputs: &mut Outputs<F>) -> Result<()> {
+		outputs.start(self);
+		// Output initial state, once (it may also be a scheduled time).
+		if self.step_count == 0 {
+			self.end_time = self.time;
+			self.next_end_time = f64::min(end_time, outputs.next_time_after(self.time));
+			outputs.output(self)?;
+		}
+		while self.time < end_time {
+			let stop = f64::min(end_time, outputs.next_time(self.time));
+			self.next_end_time = f64::min(end_time, outputs.next_time_after(stop));
+			self.advance_steps_until(stop, |s| outputs.output(s))?;
+		}
+		Ok(())
+	}
 
 	/// Advance time by exactly total_time.
 	/// Calls outfn(self) on each step, which may save output.
-	pub fn advance_with_callback<C: FnMut(&Self) -> Result<()>>(&mut self, total_time: f64, mut outfn: C) -> Result<()> {
+	pub fn advance_with_callback<C: FnMut(&Self) -> Result<()>>(&mut self, total_time: f64, outfn: C) -> Result<()> {
+		self.advance_until_with_callback(self.time + total_time, outfn)
+	}
+
+	/// Advance time up to exactly end_time.
+	/// Calls outfn(self) on each step, which may save output.
+	pub fn advance_until_with_callback<C: FnMut(&Self) -> Result<()>>(&mut self, end_time: f64, mut outfn: C) -> Result<()> {
 		// Output initial state
+		self.end_time = end_time;
+		self.next_end_time = end_time;
 		if self.step_count == 0 {
-			outfn(&self)?;
+			outfn(self)?;
+		}
+		self.advance_steps_until(end_time, outfn)
+	}
+
+	// Like advance_until_with_callback, without the initial state.
+	fn advance_steps_until<C: FnMut(&Self) -> Result<()>>(&mut self, end_time: f64, mut outfn: C) -> Result<()> {
+		self.end_time = end_time;
+		// Resuming from a state saved by outfn, e.g. a checkpoint.
+		if self.adjust_pending {
+			self.adjust_dt();
 		}
 
 		// Take normal time steps until just before the end time,
-		// then take one last step, truncated to fit total_time exactly.
-		let end_time = self.time + total_time;
+		// then take one last step, truncated to fit end_time exactly.
 		while self.time + self.dt < end_time {
 			self.step_with_dt(self.dt);
-			outfn(&self)?;
+			self.adjust_pending = true;
+			outfn(self)?;
 			self.adjust_dt();
 		}
 		let final_dt = end_time - self.time;
 		if final_dt > 0.0 {
 			self.step_with_dt(final_dt);
-			outfn(&self)?;
+			self.time = end_time; // exactly, despite round-off.
+			outfn(self)?;
 			// truncated time step is not representative,
 			// don't adjust dt based on it.
 		}
@@ -142,12 +224,19 @@ impl<F: Forces> Stepper<F> {
 
 	//
 	fn adjust_dt(&mut self) {
+		self.dt = self.adjusted_dt();
+		self.adjust_pending = false;
+	}
+
+	// dt adjusted based on the last step's error.
+	fn adjusted_dt(&self) -> f64 {
 		let mut adjust = self.target_error / self.relative_error();
 		adjust = f64::min(adjust, 1.4);
 		adjust = f64::max(adjust, 0.1);
-		self.dt *= adjust;
-		self.dt = f64::max(self.dt, self.min_dt);
-		self.dt = f64::min(self.dt, self.max_dt);
+		let mut dt = self.dt * adjust;
+		dt = f64::max(dt, self.min_dt);
+		dt = f64::min(dt, self.max_dt);
+		dt
 	}
 
 	pub fn relative_error(&self) -> f64 {
@@ -195,4 +284,65 @@ mod test {
 		check(1e-3, 3e-7);
 		check(1e-4, 3e-9);
 	}
+
+	#[test]
+	fn next_dt() {
+		let particles = vec![Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), Particle::new(0.0, vec2(0.0, 1.0), vec2(1.2, 0.0))];
+		let mut sim = Stepper::new(particles, BruteForce::new());
+		let mut steps = Vec::new();
+		sim.advance_with_callback(1.0, |s| {
+			steps.push((s.time(), s.next_dt()));
+			Ok(())
+		})
+		.unwrap();
+		// adaptive steps, the last one truncated.
+		for w in steps.windows(2) {
+			assert!((w[0].1 - (w[1].0 - w[0].0)).abs() < 1e-12, "{:?}", w);
+		}
+		assert_eq!(steps.last(), Some(&(1.0, 0.0)));
+	}
+
+	// Records the time, next_dt and trapezoid exposure weight of every step.
+	struct StepLog(std::sync::Arc<std::sync::Mutex<Vec<(f64, f64, f32)>>>, Exposure);
+
+	impl OutputSink<BruteForce> for StepLog {
+		fn step(&mut self, sim: &Stepper<BruteForce>) -> Result<()> {
+			let weight = self.1.add(sim.time(), sim.next_dt()).unwrap_or(0.0);
+			self.0.lock().unwrap().push((sim.time(), sim.next_dt(), weight));
+			Ok(())
+		}
+
+		fn output(&mut self, _sim: &Stepper<BruteForce>) -> Result<()> {
+			Ok(())
+		}
+	}
+
+	#[test]
+	fn next_dt_at_outputs() {
+		let particles = vec![Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), Particle::new(0.0, vec2(0.0, 1.0), vec2(1.2, 0.0))];
+		let mut sim = Stepper::new(particles, BruteForce::new());
+		sim.max_dt = 0.1;
+		let steps = std::sync::Arc::new(std::sync::Mutex::new(Vec::new()));
+		let log = StepLog(steps.clone(), Exposure::new().with_trapezoid(true));
+		let dir = std::env::temp_dir().join(format!("astrosim_stepper_test_{}", std::process::id()));
+		let mut outputs = Outputs::new(&dir).unwrap().with_sink(Schedule::interval(0.25).unwrap(), Box::new(log));
+		sim.advance_with_output(1.0, &mut outputs).unwrap();
+		std::fs::remove_dir_all(&dir).unwrap();
+
+		// steps land on the output times, where next_dt is the step after the output.
+		let steps = steps.lock().unwrap();
+		for &t in &[0.0, 0.25, 0.5, 0.75, 1.0] {
+			assert!(steps.iter().any(|s| s.0 == t), "no step at {}", t);
+		}
+		for w in steps.windows(2) {
+			assert!((w[0].1 - (w[1].0 - w[0].0)).abs() < 1e-12, "{:?}", w);
+		}
+		assert_eq!(steps.last().unwrap().1, 0.0);
+
+		// trapezoid weights, also at the output times.
+		for w in steps.windows(3) {
+			assert!((w[1].2 as f64 - 0.5 * (w[2].0 - w[0].0)).abs() < 1e-6, "{:?}", w);
+		}
+		assert!((steps.iter().map(|s| s.2 as f64).sum::<f64>() - 1.0).abs() < 1e-6);
+	}
 }
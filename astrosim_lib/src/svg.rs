@@ -0,0 +1,287 @@
+use super::prelude::*;
+use std::fmt::Write as _;
+use std::fs;
+use std::path::{Path, PathBuf};
+
+/// Vector (SVG) plot of a particle snapshot, for publication figures.
+///
+/// Particles are circles sized by mass (cube root, so that area ~ mass^(2/3) like planets of equal density)
+/// and coloured by group. Positions map to the w x h plot area exactly as in raster images of the same viewport
+/// (`Viewport::to_pixel`), so plots can be laid over rendered frames.
+///
+///   let meta = sim.meta();
+///   let plot = SvgPlot::new(600, 600, Viewport::from_scale(1.5))
+///       .with_group(Selection::tag(meta, "asteroid"), [40, 90, 200])
+///       .with_group(Selection::name(meta, "jupiter"), [220, 110, 20])
+///       .with_orbits(Selection::name(meta, "jupiter"))
+///       .with_axes()
+///       .with_labels();
+///   plot.save(sim.particles(), meta, "snapshot.svg")?;
+///
+#[derive(Clone)]
+pub struct SvgPlot {
+	viewport: Viewport,
+	w: u32,
+	h: u32,
+	groups: Vec<(Selection, [u8; 3])>,
+	orbits: Option<Selection>,
+	sizes: (f64, f64),
+	background: [u8; 3],
+	foreground: [u8; 3],
+	axes: bool,
+	labels: bool,
+	title: Option<String>,
+}
+
+/// Space around the plot area for axis ticks and labels, in pixels.
+const MARGIN: f64 = 40.0;
+
+impl SvgPlot {
+	/// Plot area of w x h pixels showing `viewport`, black particles on white.
+	pub fn new(w: u32, h: u32, viewport: Viewport) -> Self {
+		Self {
+			viewport,
+			w,
+			h,
+			groups: Vec::new(),
+			orbits: None,
+			sizes: (1.0, 8.0),
+			background: [255, 255, 255],
+			foreground: [0, 0, 0],
+			axes: false,
+			labels: false,
+			title: None,
+		}
+	}
+
+	/// Colour particles in `selection`. The first matching group wins, others are drawn in the foreground colour.
+	pub fn with_group(mut self, selection: Selection, color: [u8; 3]) -> Self {
+		self.groups.push((selection, color));
+		self
+	}
+
+	/// Trace the osculating orbits of the selected particles, around the most massive body.
+	pub fn with_orbits(mut self, selection: Selection) -> Self {
+		self.orbits = Some(selection);
+		self
+	}
+
+	/// Radius in pixels of massless particles and of the most massive particle (default 1 and 8).
+	pub fn with_sizes(mut self, min: f64, max: f64) -> Self {
+		self.sizes = (min, max);
+		self
+	}
+
+	pub fn with_colors(mut self, background: [u8; 3], foreground: [u8; 3]) -> Self {
+		self.background = background;
+		self.foreground = foreground;
+		self
+	}
+
+	/// Frame the plot with ticks in the viewport's (possibly rotated) coordinates.
+	pub fn with_axes(mut self) -> Self {
+		self.axes = true;
+		self
+	}
+
+	/// Label named particles.
+	pub fn with_labels(mut self) -> Self {
+		self.labels = true;
+		self
+	}
+
+	pub fn with_title(mut self, title: &str) -> Self {
+		self.title = Some(title.into());
+		self
+	}
+
+	/// The SVG document. Labels are the names in `meta` (indexed like the particles).
+	pub fn render(&self, particles: &[Particle], meta: &[Meta]) -> String {
+		let (w, h) = (self.w as usize, self.h as usize);
+		let m = if self.axes || self.title.is_some() { MARGIN } else { 0.0 };
+		let fg = hex(self.foreground);
+		let mut s = String::new();
+		let _ = writeln!(
+			s,
+			r#"<svg xmlns="http://www.w3.org/2000/svg" width="{0}" height="{1}" viewBox="0 0 {0} {1}" font-family="sans-serif" font-size="11">"#,
+			w as f64 + 2.0 * m,
+			h as f64 + 2.0 * m
+		);
+		let _ = writeln!(s, r#"<rect width="100%" height="100%" fill="{}"/>"#, hex(self.background));
+		let _ = writeln!(s, r#"<clipPath id="plot"><rect width="{}" height="{}"/></clipPath>"#, w, h);
+		let _ = writeln!(s, r#"<g transform="translate({m},{m})">"#, m = m);
+		let _ = writeln!(s, r#"<g clip-path="url(#plot)">"#);
+
+		if let Some(selection) = &self.orbits {
+			if let Some(primary) = most_massive(particles) {
+				for (i, p) in particles.iter().enumerate() {
+					if i == primary || !selection.contains(i, p) {
+						continue;
+					}
+					let points: Vec<String> = Elements::of(p, &particles[primary])
+						.path(360)
+						.iter()
+						.map(|&(pos, _)| self.pixel(particles[primary].pos + pos))
+						.filter(|pix| pix.x.is_finite() && pix.y.is_finite())
+						.map(|pix| format!("{:.2},{:.2}", pix.x, pix.y))
+						.collect();
+					let color = self.color(i, p);
+					let _ = writeln!(s, r#"<polyline points="{}" fill="none" stroke="{}" stroke-width="0.75"/>"#, points.join(" "), color);
+				}
+			}
+		}
+
+		let max_mass = particles.iter().map(|p| p.mass).fold(0.0, f64::max);
+		let (r_min, r_max) = self.sizes;
+		for (i, p) in particles.iter().enumerate() {
+			let pix = self.pixel(p.pos);
+			let r = if max_mass > 0.0 { r_min + (r_max - r_min) * f64::cbrt(p.mass / max_mass) } else { r_min };
+			let _ = writeln!(s, r#"<circle cx="{:.2}" cy="{:.2}" r="{:.2}" fill="{}"/>"#, pix.x, pix.y, r, self.color(i, p));
+			if self.labels {
+				if let Some(name) = meta.get(i).and_then(|m| m.name.as_ref()) {
+					let _ = writeln!(s, r#"<text x="{:.2}" y="{:.2}" fill="{}">{}</text>"#, pix.x + r + 2.0, pix.y - r - 2.0, fg, escape(name));
+				}
+			}
+		}
+		let _ = writeln!(s, "</g>");
+
+		if self.axes {
+			self.write_axes(&mut s);
+		}
+		if let Some(title) = &self.title {
+			let _ = writeln!(
+				s,
+				r#"<text x="{}" y="-12" text-anchor="middle" font-size="14" fill="{}">{}</text>"#,
+				w as f64 / 2.0,
+				fg,
+				escape(title)
+			);
+		}
+		let _ = writeln!(s, "</g>");
+		let _ = writeln!(s, "</svg>");
+		s
+	}
+
+	pub fn save<P: AsRef<Path>>(&self, particles: &[Particle], meta: &[Meta], file: P) -> Result<()> {
+		let file = file.as_ref();
+		fs::write(file, self.render(particles, meta)).msg(&format!("write {}", file.to_string_lossy()))
+	}
+
+	// Border with ticks every "nice" step (1, 2 or 5 times a power of 10) of viewport-local coordinates.
+	fn write_axes(&self, s: &mut String) {
+		let (w, h) = (self.w as f64, self.h as f64);
+		let fg = hex(self.foreground);
+		let _ = writeln!(s, r#"<rect width="{}" height="{}" fill="none" stroke="{}" stroke-width="1"/>"#, w, h, fg);
+		let ticks = |lo: f64, size: f64| {
+			let raw = size / 5.0;
+			let pow = 10f64.powf(raw.log10().floor());
+			let step = [1.0, 2.0, 5.0, 10.0].iter().map(|f| f * pow).find(|&s| s >= raw).unwrap_or(raw);
+			let first = (lo / step).ceil() as i64;
+			let last = ((lo + size) / step).floor() as i64;
+			(first..=last).map(move |i| i as f64 * step)
+		};
+		let v = &self.viewport;
+		let (x0, y0) = (v.center.x - v.width / 2.0, v.center.y - v.height / 2.0);
+		for x in ticks(x0, v.width) {
+			let px = (x - x0) / v.width * w;
+			let _ = writeln!(s, r#"<line x1="{0:.2}" y1="{1}" x2="{0:.2}" y2="{2}" stroke="{3}"/>"#, px, h, h + 5.0, fg);
+			let _ = writeln!(s, r#"<text x="{:.2}" y="{}" text-anchor="middle" fill="{}">{}</text>"#, px, h + 17.0, fg, tick_label(x));
+		}
+		for y in ticks(y0, v.height) {
+			let py = (y - y0) / v.height * h;
+			let _ = writeln!(s, r#"<line x1="-5" y1="{0:.2}" x2="0" y2="{0:.2}" stroke="{1}"/>"#, py, fg);
+			let _ = writeln!(
+				s,
+				r#"<text x="-8" y="{:.2}" text-anchor="end" dominant-baseline="middle" fill="{}">{}</text>"#,
+				py,
+				fg,
+				tick_label(y)
+			);
+		}
+	}
+
+	fn pixel(&self, pos: vec2) -> vec2 {
+		self.viewport.to_pixel(pos, self.w as usize, self.h as usize)
+	}
+
+	fn color(&self, i: usize, p: &Particle) -> String {
+		let color = self.groups.iter().find(|(selection, _)| selection.contains(i, p)).map(|&(_, c)| c).unwrap_or(self.foreground);
+		hex(color)
+	}
+}
+
+/// Output sink writing an SVG plot of the particles when output is scheduled: snapshot_<step>.svg.
+pub struct SvgFrames {
+	dir: PathBuf,
+	plot: SvgPlot,
+}
+
+impl SvgFrames {
+	pub fn new(dir: &Path, plot: SvgPlot) -> Self {
+		Self { dir: dir.into(), plot }
+	}
+}
+
+impl<F: Forces> OutputSink<F> for SvgFrames {
+	fn output(&mut self, sim: &Stepper<F>) -> Result<()> {
+		self.plot.save(sim.particles(), sim.meta(), self.dir.join(format!("snapshot_{:09}.svg", sim.step_count())))
+	}
+}
+
+fn hex(c: [u8; 3]) -> String {
+	format!("#{:02x}{:02x}{:02x}", c[0], c[1], c[2])
+}
+
+// Shortest representation, without float noise like 0.30000000000000004.
+fn tick_label(v: f64) -> String {
+	let s = format!("{:.6}", v);
+	let s = s.trim_end_matches('0').trim_end_matches('.');
+	if s == "-0" {
+		"0".into()
+	} else {
+		s.into()
+	}
+}
+
+fn escape(text: &str) -> String {
+	text.replace('&', "&amp;").replace('<', "&lt;").replace('>', "&gt;")
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn svg() {
+		let particles = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)),
+			Particle::new(1e-3, vec2(1.0, 0.0), vec2(0.0, 1.0)),
+			Particle::new(0.0, vec2(0.0, 0.5), vec2(-1.5, 0.0)),
+		];
+		let meta = vec![Meta::new().with_name("sun"), Meta::new().with_name("jupiter"), Meta::new().with_tag("asteroid")];
+		let viewport = Viewport::from_scale(2.0);
+		let plot = SvgPlot::new(100, 100, viewport.clone())
+			.with_group(Selection::tag(&meta, "asteroid"), [0, 0, 255])
+			.with_orbits(Selection::name(&meta, "jupiter"))
+			.with_axes()
+			.with_labels()
+			.with_title("a < b");
+		let svg = plot.render(&particles, &meta);
+
+		assert!(svg.starts_with("<svg"));
+		assert!(svg.trim_end().ends_with("</svg>"));
+		assert_eq!(svg.matches("<circle").count(), 3);
+		assert_eq!(svg.matches("<polyline").count(), 1);
+		// same position as in raster images.
+		let pix = viewport.to_pixel(particles[1].pos, 100, 100);
+		assert_eq!(pix, vec2(75.5, 50.5));
+		assert!(svg.contains(r##"<circle cx="75.50" cy="50.50" r="1.70" fill="#000000"/>"##));
+		assert!(svg.contains(r##"<circle cx="50.50" cy="63.00" r="1.00" fill="#0000ff"/>"##));
+		assert!(svg.contains(">jupiter</text>"));
+		assert!(svg.contains(">a &lt; b</text>"));
+		// ticks from -2 to 2, every 1 (x) and every 1 (y).
+		assert!(svg.contains(r##"text-anchor="middle" fill="#000000">-2</text>"##));
+		assert_eq!(svg.matches(r#"text-anchor="end""#).count(), 5);
+		assert_eq!(tick_label(0.30000000000000004), "0.3");
+	}
+}
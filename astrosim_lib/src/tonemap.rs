@@ -0,0 +1,290 @@
+use super::prelude::*;
+use std::str::FromStr;
+
+/// Maps (density) values to colours: clipping, stretch and colormap.
+///
+/// Values are first clipped to the `clip` percentiles and scaled to 0..1,
+/// then stretched (e.g. sqrt, to bring out faint structure), then colored.
+/// The default (no clipping, sqrt, gray) is what `save_density` has always written.
+///
+///   let img = ToneMap::new().with_stretch(Stretch::Asinh(0.05)).with_clip(0.0, 99.5).with_colormap(Colormap::Magma).apply(&density);
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct ToneMap {
+	pub stretch: Stretch,
+	pub clip: (f32, f32),
+	pub colormap: Colormap,
+}
+
+/// Non-linear stretch of values normalized to 0..1.
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Stretch {
+	Linear,
+	Sqrt,
+	/// ln(1 + a x) / ln(1 + a): larger `a` stretches faint values more.
+	Log(f32),
+	/// asinh(x / b) / asinh(1 / b): linear below the softening `b`, logarithmic above.
+	Asinh(f32),
+	/// Histogram equalization: all output levels are (roughly) equally common.
+	Equalize,
+}
+
+/// Maps 0..1 to colours.
+/// Viridis, magma and inferno are perceptually uniform (matplotlib's colormaps).
+#[derive(Clone, Copy, Debug, PartialEq)]
+pub enum Colormap {
+	Gray,
+	Viridis,
+	Magma,
+	Inferno,
+}
+
+impl Default for ToneMap {
+	fn default() -> Self {
+		Self {
+			stretch: Stretch::Sqrt,
+			clip: (0.0, 100.0),
+			colormap: Colormap::Gray,
+		}
+	}
+}
+
+impl ToneMap {
+	pub fn new() -> Self {
+		Self::default()
+	}
+
+	pub fn with_stretch(mut self, stretch: Stretch) -> Self {
+		self.stretch = stretch;
+		self
+	}
+
+	/// Clip values below and above these percentiles (0 <= low < high <= 100).
+	/// A low percentile of 0 maps value 0 (rather than the minimum value) to black.
+	pub fn with_clip(mut self, low: f32, high: f32) -> Self {
+		assert!(
+			0.0 <= low && low < high && high <= 100.0,
+			"ToneMap::with_clip: want 0 <= low < high <= 100, got {}, {}",
+			low,
+			high
+		);
+		self.clip = (low, high);
+		self
+	}
+
+	pub fn with_colormap(mut self, colormap: Colormap) -> Self {
+		self.colormap = colormap;
+		self
+	}
+
+	/// Values clipped, normalized to 0..1 and stretched.
+	pub fn normalize(&self, img: &Image<f32>) -> Image<f32> {
+		let (lo, hi, sorted) = self.clip_range(img);
+
+		let mut out = img.clone();
+		for v in out.pixels_mut() {
+			let x = if hi > lo { f32::clamp((*v - lo) / (hi - lo), 0.0, 1.0) } else { 0.0 };
+			*v = match self.stretch {
+				Stretch::Linear => x,
+				Stretch::Sqrt => x.sqrt(),
+				Stretch::Log(a) => f32::ln(1.0 + a * x) / f32::ln(1.0 + a),
+				Stretch::Asinh(b) => f32::asinh(x / b) / f32::asinh(1.0 / b),
+				Stretch::Equalize => x, // below
+			};
+		}
+
+		if self.stretch == Stretch::Equalize {
+			// Cumulative distribution of the clipped values,
+			// shifted so that the lowest value stays black.
+			let clipped: Vec<f32> = sorted.iter().map(|&v| f32::clamp(v, lo, hi)).collect();
+			let cdf = |v: f32| clipped.partition_point(|&c| c <= v) as f32;
+			let cdf_min = cdf(lo);
+			let n = clipped.len() as f32;
+			for (v, &orig) in out.pixels_mut().iter_mut().zip(img.pixels()) {
+				*v = if n > cdf_min { (cdf(f32::clamp(orig, lo, hi)) - cdf_min) / (n - cdf_min) } else { 0.0 };
+			}
+		}
+		out
+	}
+
+	// Values at the clip percentiles (lo, hi),
+	// and all values sorted if needed for equalization (else empty).
+	fn clip_range(&self, img: &Image<f32>) -> (f32, f32, Vec<f32>) {
+		let values = || img.pixels().iter().copied().filter(|v| !v.is_nan());
+		let (low, high) = self.clip;
+		let equalize = self.stretch == Stretch::Equalize;
+		if low <= 0.0 && high >= 100.0 && !equalize {
+			return (values().fold(0.0, f32::min), values().fold(f32::NEG_INFINITY, f32::max), Vec::new());
+		}
+
+		let mut values: Vec<f32> = values().collect();
+		let n = values.len();
+		if n == 0 {
+			return (0.0, 0.0, values);
+		}
+		let index = |p: f32| usize::min(f32::round(p / 100.0 * ((n - 1) as f32)) as usize, n - 1);
+		if equalize {
+			values.sort_by(f32::total_cmp);
+		}
+		let ih = index(high);
+		let hi = if equalize { values[ih] } else { *values.select_nth_unstable_by(ih, f32::total_cmp).1 };
+		// Below the high percentile (if selected, values[..ih] are the lower ones).
+		let lo = if low <= 0.0 {
+			values[..=ih].iter().copied().fold(0.0, f32::min)
+		} else if equalize {
+			values[index(low)]
+		} else {
+			*values[..=ih].select_nth_unstable_by(index(low), f32::total_cmp).1
+		};
+		(lo, hi, if equalize { values } else { Vec::new() })
+	}
+
+	/// Tone-mapped colour image.
+	pub fn apply(&self, img: &Image<f32>) -> image::RgbaImage {
+		let norm = self.normalize(img);
+		let (w, h) = norm.dimensions();
+		image::ImageBuffer::from_fn(w as u32, h as u32, |x, y| {
+			let [r, g, b] = self.colormap.color(norm[y as usize][x as usize]);
+			image::Rgba([r, g, b, 255])
+		})
+	}
+}
+
+impl Colormap {
+	/// Colour for `t` in 0..1 (clamped).
+	pub fn color(self, t: f32) -> [u8; 3] {
+		let t = f32::clamp(t, 0.0, 1.0) as f64;
+		let rgb = match self {
+			Colormap::Gray => [t, t, t],
+			Colormap::Viridis => poly(&VIRIDIS, t),
+			Colormap::Magma => poly(&MAGMA, t),
+			Colormap::Inferno => poly(&INFERNO, t),
+		};
+		let u8 = |c: f64| (f64::clamp(c, 0.0, 1.0) * 255.0) as u8;
+		[u8(rgb[0]), u8(rgb[1]), u8(rgb[2])]
+	}
+}
+
+// Degree 6 polynomial fits to matplotlib's colormaps, accurate to about 1%.
+// Coefficients of t^0 .. t^6, for r, g, b.
+type Poly = [[f64; 3]; 7];
+
+fn poly(c: &Poly, t: f64) -> [f64; 3] {
+	let mut rgb = [0.0; 3];
+	for (i, v) in rgb.iter_mut().enumerate() {
+		*v = c.iter().rev().fold(0.0, |acc, c| acc * t + c[i]);
+	}
+	rgb
+}
+
+const VIRIDIS: Poly = [
+	[0.2777273272234177, 0.005407344544966578, 0.3340998053353061],
+	[0.1050930431085774, 1.404613529898575, 1.384590162594685],
+	[-0.3308618287255563, 0.214847559468213, 0.09509516302823659],
+	[-4.634230498983486, -5.799100973351585, -19.33244095627987],
+	[6.228269936347081, 14.17993336680509, 56.69055260068105],
+	[4.776384997670288, -13.74514537774601, -65.35303263337234],
+	[-5.435455855934631, 4.645852612178535, 26.3124352495832],
+];
+
+const MAGMA: Poly = [
+	[-0.002136485053939582, -0.000749655052795221, -0.005386127855323933],
+	[0.2516605407371642, 0.6775232436837668, 2.494026599312351],
+	[8.353717279216625, -3.577719514958484, 0.3144679030132573],
+	[-27.66873308576866, 14.26473078096533, -13.64921318813922],
+	[52.17613981234068, -27.94360607168351, 12.94416944238394],
+	[-50.76852536473588, 29.04658282127291, 4.23415299384598],
+	[18.65570506591883, -11.48977351997711, -5.601961508734096],
+];
+
+const INFERNO: Poly = [
+	[0.0002189403691192265, 0.001651004631001012, -0.01948089843709184],
+	[0.1065134194856116, 0.5639564367884091, 3.932712388889277],
+	[11.60249308247187, -3.972853965665698, -15.9423941062914],
+	[-41.70399613139459, 17.43639888205313, 44.35414519872813],
+	[77.162935699427, -33.40235894210092, -81.80730925738993],
+	[-71.31942824499214, 32.62606426397723, 73.20951985803202],
+	[25.13112622477341, -12.24266895238567, -23.07032500287172],
+];
+
+impl FromStr for Stretch {
+	type Err = Error;
+
+	/// "linear", "sqrt", "log", "asinh" or "equalize", with default parameters.
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"linear" => Ok(Stretch::Linear),
+			"sqrt" => Ok(Stretch::Sqrt),
+			"log" => Ok(Stretch::Log(1000.0)),
+			"asinh" => Ok(Stretch::Asinh(0.05)),
+			"equalize" => Ok(Stretch::Equalize),
+			_ => err(format!("unknown stretch {:?}, want linear, sqrt, log, asinh or equalize", s)),
+		}
+	}
+}
+
+impl FromStr for Colormap {
+	type Err = Error;
+
+	fn from_str(s: &str) -> Result<Self> {
+		match s {
+			"gray" | "grey" => Ok(Colormap::Gray),
+			"viridis" => Ok(Colormap::Viridis),
+			"magma" => Ok(Colormap::Magma),
+			"inferno" => Ok(Colormap::Inferno),
+			_ => err(format!("unknown colormap {:?}, want gray, viridis, magma or inferno", s)),
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	fn ramp() -> Image<f32> {
+		let mut img = Image::new(100, 1);
+		for (i, v) in img.pixels_mut().iter_mut().enumerate() {
+			*v = (i * i) as f32;
+		}
+		img
+	}
+
+	#[test]
+	fn stretch() {
+		let img = ramp();
+		let max = 99.0 * 99.0;
+		let at = |tm: ToneMap, i: usize| tm.normalize(&img).pixels()[i];
+
+		assert_eq!(at(ToneMap::new().with_stretch(Stretch::Linear), 99), 1.0);
+		assert_eq!(at(ToneMap::new().with_stretch(Stretch::Linear), 50), 2500.0 / max);
+		assert_eq!(at(ToneMap::new(), 50), f32::sqrt(2500.0 / max));
+		assert!(at(ToneMap::new().with_stretch(Stretch::Log(1000.0)), 10) > at(ToneMap::new(), 10));
+		assert!(f32::abs(at(ToneMap::new().with_stretch(Stretch::Asinh(0.1)), 99) - 1.0) < 1e-6);
+
+		// clipping: the top percentiles saturate.
+		assert_eq!(at(ToneMap::new().with_clip(0.0, 98.0), 97), 1.0);
+		assert!(at(ToneMap::new().with_clip(0.0, 98.0), 96) < 1.0);
+		assert_eq!(at(ToneMap::new().with_clip(10.0, 100.0), 10), 0.0);
+		assert!(at(ToneMap::new().with_clip(10.0, 100.0), 11) > 0.0);
+
+		// equalization of distinct values: uniform.
+		let eq = ToneMap::new().with_stretch(Stretch::Equalize).normalize(&img);
+		assert_eq!(eq.pixels()[0], 0.0);
+		assert!(f32::abs(eq.pixels()[50] - 50.0 / 99.0) < 1e-6);
+		assert_eq!(eq.pixels()[99], 1.0);
+	}
+
+	// Compare to matplotlib's colormaps.
+	#[test]
+	fn colormaps() {
+		let close = |a: [u8; 3], b: [f32; 3]| a.iter().zip(&b).all(|(&a, &b)| f32::abs(a as f32 / 255.0 - b) < 0.03);
+		assert!(close(Colormap::Viridis.color(0.0), [0.267, 0.005, 0.329]));
+		assert!(close(Colormap::Viridis.color(0.5), [0.128, 0.567, 0.551]));
+		assert!(close(Colormap::Viridis.color(1.0), [0.993, 0.906, 0.144]));
+		assert!(close(Colormap::Magma.color(0.5), [0.716, 0.215, 0.475]));
+		assert!(close(Colormap::Magma.color(1.0), [0.987, 0.991, 0.749]));
+		assert!(close(Colormap::Inferno.color(0.5), [0.735, 0.216, 0.330]));
+		assert!(close(Colormap::Inferno.color(1.0), [0.988, 0.998, 0.645]));
+		assert_eq!(Colormap::Gray.color(2.0), [255, 255, 255]);
+	}
+}
@@ -0,0 +1,185 @@
+use super::prelude::*;
+use std::collections::VecDeque;
+
+/// Trails of the recent positions of selected particles, drawn over rendered frames
+/// as anti-aliased polylines that fade out towards the oldest position.
+///
+/// Positions are recorded in pixel coordinates of the projection they are recorded with,
+/// so that in a co-rotating frame the trails show the paths in that frame (e.g. horseshoe orbits).
+///
+///   let mut trails = Trails::new(Selection::name(sim.meta(), "jupiter"), 500).with_color([255, 160, 60]);
+///   sim.advance_with_callback(10.0, |s| Ok(trails.record(s.particles(), &viewport, w, h)))?;
+///   trails.draw(&mut frame);
+///
+#[derive(Clone)]
+pub struct Trails {
+	selection: Selection,
+	len: usize,
+	spacing: f64,
+	color: [u8; 3],
+	alpha: f32,
+	// Per selected particle (by index): recorded positions, oldest first, and the latest position.
+	trails: Vec<(usize, VecDeque<vec2>, vec2)>,
+	// Number of particles the trails were started with.
+	particle_count: usize,
+}
+
+impl Trails {
+	/// Trails of up to `len` points per particle.
+	pub fn new(selection: Selection, len: usize) -> Self {
+		Self {
+			selection,
+			len,
+			spacing: 1.0,
+			color: [255, 255, 255],
+			alpha: 1.0,
+			trails: Vec::new(),
+			particle_count: 0,
+		}
+	}
+
+	/// Only record a new point once the particle has moved this many pixels from the previous one (default 1),
+	/// so that the trail length does not depend on the time step.
+	pub fn with_spacing(mut self, pixels: f64) -> Self {
+		self.spacing = pixels;
+		self
+	}
+
+	pub fn with_color(mut self, color: [u8; 3]) -> Self {
+		self.color = color;
+		self
+	}
+
+	/// Opacity of the newest part of the trail.
+	pub fn with_alpha(mut self, alpha: f32) -> Self {
+		self.alpha = alpha;
+		self
+	}
+
+	/// Record the current positions, as projected on a w x h image.
+	/// Particles are identified by index: if their number changes, the trails start over.
+	pub fn record<P: Projection + ?Sized>(&mut self, particles: &[Particle], projection: &P, w: usize, h: usize) {
+		if particles.len() != self.particle_count {
+			self.trails.clear();
+			self.particle_count = particles.len();
+		}
+		if self.trails.is_empty() {
+			self.trails = (0..particles.len())
+				.filter(|&i| self.selection.contains(i, &particles[i]))
+				.map(|i| (i, VecDeque::new(), vec2::ZERO))
+				.collect();
+		}
+		for (i, points, last) in &mut self.trails {
+			let pos = projection.project(&particles[*i], w, h);
+			*last = pos;
+			match points.back() {
+				Some(&prev) if (pos - prev).len() < self.spacing => (),
+				_ => {
+					points.push_back(pos);
+					if points.len() > self.len {
+						points.pop_front();
+					}
+				}
+			}
+		}
+	}
+
+	/// Draw the trails, fading linearly from the newest to the oldest point.
+	/// Segments longer than half the image (e.g. wrapping around in polar projection) are not drawn.
+	pub fn draw(&self, img: &mut image::RgbaImage) {
+		let max_len = 0.5 * f64::max(img.width() as f64, img.height() as f64);
+		let color = image::Rgba([self.color[0], self.color[1], self.color[2], 255]);
+		for (_, points, last) in &self.trails {
+			let n = points.len();
+			for (k, (&a, &b)) in points.iter().zip(points.iter().skip(1).chain(Some(last))).enumerate() {
+				let segment = (b - a).len();
+				if segment.is_finite() && segment < max_len {
+					let alpha = self.alpha * (k + 1) as f32 / n as f32;
+					img.draw_line_aa(a, b, color, alpha);
+				}
+			}
+		}
+	}
+
+	/// Forget all recorded positions (e.g. after the viewport changed).
+	pub fn clear(&mut self) {
+		self.trails.clear();
+	}
+}
+
+/// Draw the osculating Keplerian orbit of `particle` around `primary`, anti-aliased, blended over the image.
+/// Unbound orbits are drawn out to where they leave the image.
+pub fn draw_orbit<P: Projection + ?Sized>(img: &mut image::RgbaImage, particle: &Particle, primary: &Particle, projection: &P, color: [u8; 3], alpha: f32) {
+	let el = Elements::of(particle, primary);
+	let (w, h) = (img.width() as usize, img.height() as usize);
+	// enough segments for a smooth curve on the image.
+	let (ppu_x, ppu_y) = projection.pixels_per_unit(w, h);
+	let size = f64::max(ppu_x.abs(), ppu_y.abs()) * el.p / (1.0 - el.e.min(0.9));
+	let n = usize::clamp((2.0 * size) as usize, 64, 10_000);
+	let max_len = 0.5 * f64::max(w as f64, h as f64);
+	let points: Vec<vec2> = el
+		.path(n)
+		.iter()
+		.map(|&(pos, vel)| projection.project(&Particle::new(particle.mass, primary.pos + pos, primary.vel + vel), w, h))
+		.collect();
+	for segment in points.windows(2) {
+		let len = (segment[1] - segment[0]).len();
+		if len.is_finite() && len < max_len {
+			img.draw_line_aa(segment[0], segment[1], image::Rgba([color[0], color[1], color[2], 255]), alpha);
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn trails() {
+		let viewport = Viewport::from_scale(2.0);
+		let (w, h) = (40, 40);
+		let mut p = vec![
+			Particle::new(1.0, vec2(0.0, 0.0), vec2(0.0, 0.0)), // sun
+			Particle::new(0.0, vec2(1.0, 0.0), vec2(0.0, 1.0)), // planet
+		];
+		let meta = vec![Meta::new().with_name("sun"), Meta::new().with_name("planet")];
+		let mut trails = Trails::new(Selection::name(&meta, "planet"), 4);
+		for i in 0..10 {
+			p[1].pos = vec2(-1.0 + 0.2 * i as f64, 0.0);
+			trails.record(&p, &viewport, w as usize, h as usize);
+			trails.record(&p, &viewport, w as usize, h as usize); // not moved: not recorded again
+		}
+		let (_, points, last) = &trails.trails[0];
+		assert_eq!(trails.trails.len(), 1);
+		assert_eq!(points.len(), 4);
+		assert_eq!(*last, points[3]);
+
+		let mut img = image::RgbaImage::new(w, h);
+		trails.draw(&mut img);
+		// x from 0.2 (pixel 22) to 0.8 (pixel 28) at y = 0 (pixel 20), fading out to the left.
+		let alpha = |x| img.get_pixel(x, 20).0[3];
+		assert_eq!(alpha(29), 0);
+		assert!(alpha(27) > alpha(24) && alpha(24) > alpha(22) && alpha(22) > 0);
+		assert_eq!(alpha(20), 0);
+
+		// circular orbit of radius 1: 10 pixels around the center.
+		let mut img = image::RgbaImage::new(w, h);
+		p[1].pos = vec2(1.0, 0.0);
+		draw_orbit(&mut img, &p[1], &p[0], &viewport, [255, 255, 255], 1.0);
+		assert!(img.get_pixel(30, 20).0[3] > 100 || img.get_pixel(29, 20).0[3] > 100);
+		assert!(img.get_pixel(20, 10).0[3] > 100 || img.get_pixel(20, 9).0[3] > 100);
+		assert_eq!(img.get_pixel(20, 20).0[3], 0);
+
+		// by semi-major axis, an eccentric orbit is a horizontal line (a = 1 / (2 - 1.2^2)).
+		let mut img = image::RgbaImage::new(w, h);
+		p[1].vel = vec2(0.0, 1.2);
+		draw_orbit(&mut img, &p[1], &p[0], &Polar::new(Radial::SemiMajorAxis, 0.0, 4.0), [255, 255, 255], 1.0);
+		let rows: Vec<u32> = img.enumerate_pixels().filter(|(_, _, c)| c.0[3] > 0).map(|(_, y, _)| y).collect();
+		assert!(rows.len() > w as usize);
+		assert!(rows.iter().all(|&y| y == 17 || y == 18), "{:?}", rows);
+
+		// fewer particles (e.g. after a merger): the trails start over.
+		trails.record(&p[..1], &viewport, w as usize, h as usize);
+		assert!(trails.trails.is_empty());
+	}
+}
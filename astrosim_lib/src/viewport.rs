@@ -0,0 +1,120 @@
+use super::prelude::*;
+
+/// The part of the world that is rendered: a (possibly rotated) rectangle.
+///
+/// World coordinates map to continuous pixel coordinates, where pixel (i, j)
+/// covers [i, i+1) x [j, j+1). Pixel x follows the viewport's x axis, pixel y its y axis.
+/// The viewport center maps to (w/2 + 0.5, h/2 + 0.5), as density images have always been rendered:
+/// the center of pixel (w/2, h/2) for even image sizes.
+///
+///   let (w, h) = (640, 480);
+///   let viewport = Viewport::from_scale(2.5).fit(w, h).with_rotation(PI / 4.0);
+///
+#[derive(Clone, Debug, PartialEq)]
+pub struct Viewport {
+	pub center: vec2,
+	pub width: f64,
+	pub height: f64,
+	/// Counter-clockwise rotation of the viewport (radians), relative to the world axes.
+	pub rotation: f64,
+}
+
+impl Viewport {
+	/// Viewport of `width` x `height` world units, centered on the origin.
+	pub fn new(width: f64, height: f64) -> Self {
+		Self {
+			center: vec2::ZERO,
+			width,
+			height,
+			rotation: 0.0,
+		}
+	}
+
+	/// Square viewport from -scale to scale, as rendered by `scale` arguments in the past.
+	pub fn from_scale(scale: f64) -> Self {
+		Self::new(2.0 * scale, 2.0 * scale)
+	}
+
+	pub fn with_center(mut self, center: vec2) -> Self {
+		self.center = center;
+		self
+	}
+
+	pub fn with_rotation(mut self, rotation: f64) -> Self {
+		self.rotation = rotation;
+		self
+	}
+
+	/// Set the height so that width / height = `aspect`.
+	pub fn with_aspect(mut self, aspect: f64) -> Self {
+		self.height = self.width / aspect;
+		self
+	}
+
+	/// Set the aspect ratio to that of a `w` x `h` pixel image, so that pixels are square.
+	pub fn fit(self, w: u32, h: u32) -> Self {
+		self.with_aspect(w as f64 / h as f64)
+	}
+
+	/// Width / height.
+	pub fn aspect(&self) -> f64 {
+		self.width / self.height
+	}
+
+	/// Continuous pixel coordinates of world position `pos`, in a `w` x `h` pixel image.
+	pub fn to_pixel(&self, pos: vec2, w: usize, h: usize) -> vec2 {
+		let d = pos - self.center;
+		let (sin, cos) = self.rotation.sin_cos();
+		let local = vec2(d.x * cos + d.y * sin, -d.x * sin + d.y * cos);
+		vec2((local.x / self.width + 0.5) * (w as f64) + 0.5, (local.y / self.height + 0.5) * (h as f64) + 0.5)
+	}
+
+	/// World position of continuous pixel coordinates `pix` (inverse of `to_pixel`).
+	pub fn to_world(&self, pix: vec2, w: usize, h: usize) -> vec2 {
+		let local = vec2(((pix.x - 0.5) / (w as f64) - 0.5) * self.width, ((pix.y - 0.5) / (h as f64) - 0.5) * self.height);
+		let (sin, cos) = self.rotation.sin_cos();
+		self.center + vec2(local.x * cos - local.y * sin, local.x * sin + local.y * cos)
+	}
+
+	/// Pixel (x, y) containing world position `pos`, if inside the image.
+	pub fn pixel_index(&self, pos: vec2, w: usize, h: usize) -> Option<(usize, usize)> {
+		let pix = self.to_pixel(pos, w, h);
+		let (x, y) = (pix.x.floor(), pix.y.floor());
+		if x >= 0.0 && x < (w as f64) && y >= 0.0 && y < (h as f64) {
+			Some((x as usize, y as usize))
+		} else {
+			None
+		}
+	}
+}
+
+#[cfg(test)]
+mod test {
+	use super::*;
+
+	#[test]
+	fn viewport() {
+		let v = Viewport::from_scale(2.0).fit(400, 200);
+		assert_eq!((v.width, v.height, v.aspect()), (4.0, 2.0, 2.0));
+		assert_eq!(v.pixel_index(vec2(0.0, 0.0), 400, 200), Some((200, 100)));
+		assert_eq!(v.pixel_index(vec2(0.004, 0.004), 400, 200), Some((200, 100)));
+		assert_eq!(v.pixel_index(vec2(-0.006, 0.0), 400, 200), Some((199, 100)));
+		assert_eq!(v.pixel_index(vec2(-2.0, -1.0), 400, 200), Some((0, 0)));
+		assert_eq!(v.pixel_index(vec2(1.989, 0.989), 400, 200), Some((399, 199)));
+		assert_eq!(v.pixel_index(vec2(0.0, 0.995), 400, 200), None);
+		assert_eq!(v.pixel_index(vec2(-2.006, 0.0), 400, 200), None);
+
+		let v = v.with_center(vec2(1.0, 1.0));
+		assert_eq!(v.pixel_index(vec2(1.0, 1.0), 400, 200), Some((200, 100)));
+
+		// rotated a quarter turn: world +y is the viewport's +x.
+		let v = Viewport::new(4.0, 2.0).with_rotation(PI / 2.0);
+		let p = v.to_pixel(vec2(0.0, 1.5), 400, 200);
+		assert!((p - vec2(350.5, 100.5)).len() < 1e-9);
+		assert_eq!(v.pixel_index(vec2(1.5, 0.0), 400, 200), None);
+
+		let v = v.with_center(vec2(0.3, -0.2)).with_rotation(0.7);
+		let w = vec2(0.5, 0.25);
+		assert!((v.to_world(v.to_pixel(w, 400, 200), 400, 200) - w).len() < 1e-12);
+	}
+}
@@ -4,6 +4,8 @@
 //!  * A corresponding `Result` type.
 //!  * The `ErrorMessage` trait, adding a method `msg(...)` to Results.
 //!
+#![allow(clippy::tabs_in_doc_comments)]
+
 mod error;
 mod errormessage;
 mod result;